@@ -0,0 +1,18 @@
+use gluesql_discord_storage::emulator::Emulator;
+
+/// `DISCORD_EMULATOR_ADDR` defaults to `127.0.0.1:3000`,
+/// `DISCORD_EMULATOR_GUILD` to the guild `main.rs` stores its tables in.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    color_eyre::install().unwrap();
+
+    let addr = std::env::var("DISCORD_EMULATOR_ADDR")
+        .unwrap_or_else(|_| "127.0.0.1:3000".to_owned())
+        .parse()
+        .expect("invalid DISCORD_EMULATOR_ADDR");
+    let guild_name = std::env::var("DISCORD_EMULATOR_GUILD")
+        .unwrap_or_else(|_| "GlueSQL Storage Test".to_owned());
+
+    Emulator::new(guild_name).serve(addr).await.unwrap();
+}
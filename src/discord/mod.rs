@@ -1,20 +1,33 @@
 use std::{collections::HashMap, sync::Arc};
 
+use async_trait::async_trait;
 use eyre::Context;
 use serenity::{
     builder::CreateChannel,
     client::ClientBuilder,
-    futures::Stream,
-    http::{CacheHttp, Http, HttpBuilder},
+    futures::{
+        stream::{self, LocalBoxStream},
+        Stream, StreamExt, TryStreamExt,
+    },
+    http::{routing::Route, CacheHttp, Http, HttpBuilder},
     model::{
-        prelude::{Channel, ChannelId, GuildChannel, GuildId, GuildInfo, Message, MessageId},
+        prelude::{
+            Attachment, AttachmentType, Channel, ChannelId, GuildChannel, GuildId, GuildInfo,
+            Message, MessageId, ReactionType,
+        },
         user::CurrentUser,
     },
     prelude::GatewayIntents,
     Client,
 };
 
-use crate::{debug, storage};
+use crate::{
+    debug, storage,
+    transport::{MessageFile, Transport},
+};
+
+/// Discord returns at most this many messages per history request
+const MESSAGES_PAGE_LIMIT: u64 = 100;
 
 pub struct Discord {
     pub client: Client,
@@ -34,6 +47,25 @@ impl Discord {
     pub async fn new(token: impl AsRef<str>) -> Self {
         let http = HttpBuilder::new(token.as_ref()).build();
 
+        Self::from_http(http).await
+    }
+
+    /// Sends REST API requests to `base_url` instead of `https://discord.com`,
+    /// e.g. `http://127.0.0.1:3000` for a local emulator.
+    ///
+    /// serenity only honors the proxy with its ratelimiter disabled,
+    /// so rate limiting is left to the server at `base_url`.
+    pub async fn with_base_url(token: impl AsRef<str>, base_url: impl Into<String>) -> Self {
+        let http = HttpBuilder::new(token.as_ref())
+            .proxy(base_url)
+            .expect("invalid base url")
+            .ratelimiter_disabled(true)
+            .build();
+
+        Self::from_http(http).await
+    }
+
+    async fn from_http(http: Http) -> Self {
         let client = ClientBuilder::new_with_http(http, GatewayIntents::MESSAGE_CONTENT)
             .await
             .expect("check bot token / failed create client");
@@ -57,8 +89,14 @@ impl Discord {
         Ok(storage::DiscordStorage::new(self, storage_guild_id))
     }
 
+    /// `DISCORD_API_BASE_URL` is optional and defaults to `https://discord.com`
     pub async fn from_env() -> Self {
-        Self::new(std::env::var("DISCORD_BOT_TOKEN").unwrap()).await
+        let token = std::env::var("DISCORD_BOT_TOKEN").unwrap();
+
+        match std::env::var("DISCORD_API_BASE_URL") {
+            Ok(base_url) => Self::with_base_url(token, base_url).await,
+            Err(_) => Self::new(token).await,
+        }
     }
 
     pub fn latest_message_stream(
@@ -69,6 +107,50 @@ impl Discord {
         Box::pin(channel_id.messages_iter(http))
     }
 
+    /// pages through the history after `after` with the `after` query parameter
+    pub fn oldest_message_stream(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> impl Stream<Item = eyre::Result<Message>> + '_ {
+        stream::try_unfold(Some(after), move |after| async move {
+            let after = match after {
+                Some(after) => after,
+                None => return Ok::<_, eyre::Report>(None),
+            };
+
+            let (page, next) = self.messages_after(channel_id, after).await?;
+
+            Ok(Some((stream::iter(page.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+    }
+
+    /// one page of the history after `after`, oldest first, with the message the next page
+    /// starts after, `None` when this is the last page
+    pub async fn messages_after(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> eyre::Result<(Vec<Message>, Option<MessageId>)> {
+        let mut page = debug::time!("messages_after", {
+            channel_id
+                .messages(self.http(), |builder| {
+                    builder.after(after).limit(MESSAGES_PAGE_LIMIT)
+                })
+                .await
+                .context("failed messages_after")?
+        });
+        page.sort_by_key(|message| message.id);
+
+        let next = match page.last() {
+            Some(message) if page.len() as u64 == MESSAGES_PAGE_LIMIT => Some(message.id),
+            _ => None,
+        };
+
+        Ok((page, next))
+    }
+
     pub fn http(&self) -> &Http {
         self.client.cache_and_http.http()
     }
@@ -156,6 +238,56 @@ impl Discord {
         })
     }
 
+    pub async fn send_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        content: impl ToString,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        debug::time!("send_message_with_files", {
+            let files = files.into_iter().map(attachment_type);
+
+            channel_id
+                .send_files(self.http(), files, |m| m.content(content))
+                .await
+                .context("failed send_message_with_files")
+        })
+    }
+
+    /// replaces every attachment of the message with `files`
+    pub async fn edit_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        message_id: impl Into<MessageId>,
+        content: impl ToString,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        debug::time!("edit_message_with_files", {
+            channel_id
+                .edit_message(self.http(), message_id, |m| {
+                    m.content(content);
+                    // `attachments` lists the existing attachments to keep
+                    m.0.insert("attachments", serde_json::Value::Array(vec![]));
+                    for file in files {
+                        m.attachment(attachment_type(file));
+                    }
+
+                    m
+                })
+                .await
+                .context("failed edit_message_with_files")
+        })
+    }
+
+    pub async fn download_attachment(&self, attachment: &Attachment) -> eyre::Result<Vec<u8>> {
+        debug::time!("download_attachment", {
+            attachment
+                .download()
+                .await
+                .context("failed download_attachment")
+        })
+    }
+
     pub async fn delete_message(
         &self,
         channel_id: ChannelId,
@@ -169,6 +301,61 @@ impl Discord {
         })
     }
 
+    pub async fn create_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        debug::time!("create_reaction", {
+            self.http()
+                .create_reaction(channel_id.0, message_id.0, &reaction)
+                .await
+                .context("failed create_reaction")
+        })
+    }
+
+    pub async fn delete_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        debug::time!("delete_reaction", {
+            self.http()
+                .delete_reaction(channel_id.0, message_id.0, None, &reaction)
+                .await
+                .context("failed delete_reaction")
+        })
+    }
+
+    /// bulk delete of up to 100 messages younger than 14 days
+    pub async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    ) -> eyre::Result<()> {
+        debug::time!("delete_messages", {
+            channel_id
+                .delete_messages(self.http(), message_ids)
+                .await
+                .context("failed delete_messages")
+        })
+    }
+
+    /// remaining requests of serenity's bucket for the create message route of `channel_id`
+    pub async fn send_capacity(&self, channel_id: ChannelId) -> Option<usize> {
+        let routes = self.http().ratelimiter.routes();
+        let bucket = routes
+            .read()
+            .await
+            .get(&Route::ChannelsIdMessages(channel_id.0))
+            .cloned()?;
+        let bucket = bucket.lock().await;
+
+        (bucket.limit() > 0).then(|| usize::try_from(bucket.remaining()).unwrap_or_default())
+    }
+
     pub async fn get_guild_info(&self, guild_name: impl AsRef<str>) -> eyre::Result<GuildInfo> {
         debug::time!("get_guild_info", {
             self.current_user
@@ -228,6 +415,20 @@ impl Discord {
         })
     }
 
+    /// required Manage Channels permission
+    pub async fn rename_channel(
+        &self,
+        channel_id: ChannelId,
+        name: impl ToString,
+    ) -> eyre::Result<GuildChannel> {
+        debug::time!("rename_channel", {
+            channel_id
+                .edit(self.http(), |f| f.name(name))
+                .await
+                .context("failed rename_channel")
+        })
+    }
+
     pub async fn delete_channel(&self, channel_id: ChannelId) -> eyre::Result<Channel> {
         debug::time!("delete_channel", {
             channel_id
@@ -238,6 +439,178 @@ impl Discord {
     }
 }
 
+fn attachment_type(file: MessageFile) -> AttachmentType<'static> {
+    AttachmentType::Bytes {
+        data: file.data.into(),
+        filename: file.filename,
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for Discord {
+    fn serenity_cache(&self) -> Arc<serenity::cache::Cache> {
+        Discord::serenity_cache(self)
+    }
+
+    fn latest_message_stream(
+        &self,
+        channel_id: ChannelId,
+    ) -> LocalBoxStream<'_, eyre::Result<Message>> {
+        Discord::latest_message_stream(self, channel_id)
+            .map(|message| message.context("failed latest_message_stream"))
+            .boxed_local()
+    }
+
+    fn oldest_message_stream(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> LocalBoxStream<'_, eyre::Result<Message>> {
+        Discord::oldest_message_stream(self, channel_id, after).boxed_local()
+    }
+
+    async fn messages_after(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> eyre::Result<(Vec<Message>, Option<MessageId>)> {
+        Discord::messages_after(self, channel_id, after).await
+    }
+
+    async fn get_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> eyre::Result<Message> {
+        Discord::get_message(self, channel_id, message_id).await
+    }
+
+    async fn get_pins(&self, channel_id: ChannelId) -> eyre::Result<Vec<Message>> {
+        Discord::get_pins(self, channel_id).await
+    }
+
+    async fn set_pin(&self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()> {
+        Discord::set_pin(self, channel_id, message_id).await
+    }
+
+    async fn set_unpin(&self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()> {
+        Discord::set_unpin(self, channel_id, message_id).await
+    }
+
+    async fn send_message(&self, channel_id: ChannelId, content: String) -> eyre::Result<Message> {
+        Discord::send_message(self, channel_id, content).await
+    }
+
+    async fn edit_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+    ) -> eyre::Result<Message> {
+        Discord::edit_message(self, channel_id, message_id, content).await
+    }
+
+    async fn send_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        Discord::send_message_with_files(self, channel_id, content, files).await
+    }
+
+    async fn edit_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        Discord::edit_message_with_files(self, channel_id, message_id, content, files).await
+    }
+
+    async fn download_attachment(&self, attachment: &Attachment) -> eyre::Result<Vec<u8>> {
+        Discord::download_attachment(self, attachment).await
+    }
+
+    async fn delete_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> eyre::Result<()> {
+        Discord::delete_message(self, channel_id, message_id).await
+    }
+
+    async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    ) -> eyre::Result<()> {
+        Discord::delete_messages(self, channel_id, message_ids).await
+    }
+
+    async fn create_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        Discord::create_reaction(self, channel_id, message_id, reaction).await
+    }
+
+    async fn delete_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        Discord::delete_reaction(self, channel_id, message_id, reaction).await
+    }
+
+    async fn send_capacity(&self, channel_id: ChannelId) -> Option<usize> {
+        Discord::send_capacity(self, channel_id).await
+    }
+
+    async fn get_channels(
+        &self,
+        guild_id: GuildId,
+    ) -> eyre::Result<HashMap<ChannelId, GuildChannel>> {
+        Discord::get_channels(self, guild_id).await
+    }
+
+    async fn get_channel_id(
+        &self,
+        guild_id: GuildId,
+        channel_name: &str,
+    ) -> eyre::Result<Option<ChannelId>> {
+        Discord::get_channel_id(self, guild_id, channel_name).await
+    }
+
+    async fn create_channel(
+        &self,
+        guild_id: GuildId,
+        builder: CreateChannel,
+    ) -> eyre::Result<GuildChannel> {
+        Discord::create_channel(self, guild_id, |f| {
+            f.0.extend(builder.0);
+            f
+        })
+        .await
+    }
+
+    async fn rename_channel(
+        &self,
+        channel_id: ChannelId,
+        name: String,
+    ) -> eyre::Result<GuildChannel> {
+        Discord::rename_channel(self, channel_id, name).await
+    }
+
+    async fn delete_channel(&self, channel_id: ChannelId) -> eyre::Result<Channel> {
+        Discord::delete_channel(self, channel_id).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use serenity::futures::StreamExt;
@@ -0,0 +1,566 @@
+//! Local HTTP server speaking the subset of the Discord v10 REST API used by [`crate::Discord`].
+//!
+//! Point serenity at it with [`crate::Discord::with_base_url`] to run the full
+//! `Discord` → `DiscordStorage` → `Glue` path on a machine with no network.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    body,
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::{json, Value as Json};
+use serenity::{
+    builder::CreateChannel,
+    model::prelude::{AttachmentId, ChannelId, EmojiId, GuildId, MessageId, ReactionType},
+};
+
+use crate::transport::{
+    fake::{FakeApiError, FakeGuild, MESSAGES_PAGE_LIMIT},
+    MessageFile,
+};
+
+#[derive(Clone)]
+pub struct Emulator {
+    guild: Arc<Mutex<FakeGuild>>,
+}
+
+impl Emulator {
+    pub fn new(guild_name: impl Into<String>) -> Self {
+        Self {
+            guild: Arc::new(Mutex::new(FakeGuild::new(guild_name))),
+        }
+    }
+
+    pub fn guild(&self) -> Arc<Mutex<FakeGuild>> {
+        Arc::clone(&self.guild)
+    }
+
+    /// binds `addr` and returns the bound address with the server future to drive,
+    /// attachment urls handed out from then on point at the bound address
+    pub fn bind(
+        self,
+        addr: SocketAddr,
+    ) -> eyre::Result<(SocketAddr, impl Future<Output = eyre::Result<()>>)> {
+        let guild = self.guild();
+        let make_service = make_service_fn(move |_| {
+            let emulator = self.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let emulator = emulator.clone();
+
+                    async move { Ok::<_, Infallible>(emulator.handle(request).await) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        let local_addr = server.local_addr();
+        guild
+            .lock()
+            .expect("emulator guild lock poisoned")
+            .set_cdn_url(format!("http://{local_addr}"));
+
+        Ok((local_addr, async move { server.await.map_err(Into::into) }))
+    }
+
+    pub async fn serve(self, addr: SocketAddr) -> eyre::Result<()> {
+        let (local_addr, server) = self.bind(addr)?;
+        tracing::info!("discord emulator listening on http://{local_addr}");
+
+        server.await
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request.uri().path().to_owned();
+        let query = request.uri().query().map(parse_query).unwrap_or_default();
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        let body = match body::to_bytes(request.into_body()).await {
+            Ok(body) => body,
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, 50109, &err.to_string()),
+        };
+
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .skip_while(|segment| *segment == "api" || is_api_version(segment))
+            .collect::<Vec<_>>();
+
+        tracing::debug!("{method} {path}");
+
+        if let (&Method::GET, ["attachments", _, attachment_id, _]) = (&method, &segments[..]) {
+            return self.attachment(attachment_id);
+        }
+
+        match self.route(&method, &segments, &query, content_type.as_deref(), &body) {
+            Ok(Some(json)) => json_response(StatusCode::OK, &json),
+            Ok(None) => Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .expect("valid response"),
+            Err(err) => match err.downcast_ref::<FakeApiError>() {
+                Some(api_error) => error_response(
+                    StatusCode::from_u16(api_error.status()).expect("valid status"),
+                    api_error.code(),
+                    &api_error.to_string(),
+                ),
+                None => error_response(StatusCode::BAD_REQUEST, 0, &err.to_string()),
+            },
+        }
+    }
+
+    /// CDN download of an uploaded attachment
+    fn attachment(&self, attachment_id: &str) -> Response<Body> {
+        let guild = self.guild.lock().expect("emulator guild lock poisoned");
+        let data = attachment_id
+            .parse()
+            .ok()
+            .and_then(|attachment_id| guild.attachment(AttachmentId(attachment_id)));
+
+        match data {
+            Some(data) => Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .body(Body::from(data.to_vec()))
+                .expect("valid response"),
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .expect("valid response"),
+        }
+    }
+
+    fn route(
+        &self,
+        method: &Method,
+        segments: &[&str],
+        query: &HashMap<String, String>,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> eyre::Result<Option<Json>> {
+        let mut guild = self.guild.lock().expect("emulator guild lock poisoned");
+
+        let json = match (method, segments) {
+            (&Method::GET, ["gateway"]) => json!({ "url": "wss://gateway.discord.gg" }),
+            (&Method::GET, ["users", "@me"]) => {
+                let mut user = serde_json::to_value(guild.current_user())?;
+                user["mfa_enabled"] = Json::Bool(false);
+                user["verified"] = Json::Bool(true);
+
+                user
+            }
+            (&Method::GET, ["users", "@me", "guilds"]) => json!([{
+                "id": guild.id().0.to_string(),
+                "name": guild.name(),
+                "icon": null,
+                "owner": true,
+                "permissions": "8",
+            }]),
+            (&Method::GET, ["guilds", guild_id]) => {
+                let guild_id = parse_guild_id(&guild, guild_id)?;
+
+                json!({
+                    "id": guild_id.0.to_string(),
+                    "name": guild.name(),
+                    "icon": null,
+                    "splash": null,
+                    "discovery_splash": null,
+                    "owner_id": guild.current_user().id.0.to_string(),
+                    "afk_channel_id": null,
+                    "afk_timeout": 300,
+                    "verification_level": 0,
+                    "default_message_notifications": 0,
+                    "explicit_content_filter": 0,
+                    "roles": [],
+                    "emojis": [],
+                    "stickers": [],
+                    "features": [],
+                    "mfa_level": 0,
+                    "system_channel_id": null,
+                    "system_channel_flags": 0,
+                    "rules_channel_id": null,
+                    "public_updates_channel_id": null,
+                    "description": null,
+                    "premium_tier": 0,
+                    "premium_subscription_count": 0,
+                    "banner": null,
+                    "vanity_url_code": null,
+                    "nsfw_level": 0,
+                })
+            }
+            (&Method::GET, ["guilds", guild_id, "channels"]) => {
+                parse_guild_id(&guild, guild_id)?;
+
+                serde_json::to_value(guild.channels().into_values().collect::<Vec<_>>())?
+            }
+            (&Method::POST, ["guilds", guild_id, "channels"]) => {
+                parse_guild_id(&guild, guild_id)?;
+
+                let fields = serde_json::from_slice::<HashMap<String, Json>>(body)?;
+                let mut builder = CreateChannel::default();
+                for (key, value) in fields {
+                    if let Some(key) = create_channel_field(&key) {
+                        builder.0.insert(key, value);
+                    }
+                }
+
+                serde_json::to_value(guild.create_channel(builder)?)?
+            }
+            (&Method::PATCH, ["channels", channel_id]) => {
+                let fields = serde_json::from_slice::<HashMap<String, Json>>(body)?;
+                let name = fields
+                    .get("name")
+                    .and_then(Json::as_str)
+                    .ok_or_else(|| eyre::eyre!("only channel renames are supported"))?;
+
+                serde_json::to_value(guild.rename_channel(parse_channel_id(channel_id)?, name)?)?
+            }
+            (&Method::DELETE, ["channels", channel_id]) => {
+                serde_json::to_value(guild.delete_channel(parse_channel_id(channel_id)?)?)?
+            }
+            (&Method::GET, ["channels", channel_id, "messages"]) => {
+                let limit = match query.get("limit") {
+                    Some(limit) => limit.parse::<usize>()?.min(MESSAGES_PAGE_LIMIT),
+                    None => 50,
+                };
+                let before = query
+                    .get("before")
+                    .map(|id| parse_message_id(id))
+                    .transpose()?;
+                let after = query
+                    .get("after")
+                    .map(|id| parse_message_id(id))
+                    .transpose()?;
+
+                serde_json::to_value(guild.messages_page(
+                    parse_channel_id(channel_id)?,
+                    before,
+                    after,
+                    limit,
+                )?)?
+            }
+            (&Method::POST, ["channels", channel_id, "messages"]) => {
+                let channel_id = parse_channel_id(channel_id)?;
+                let body = MessageBody::parse(content_type, body)?;
+                let content = body.content();
+
+                let message = if body.files.is_empty() {
+                    guild.send_message(channel_id, content)?
+                } else {
+                    guild.send_message_with_files(channel_id, content, body.files)?
+                };
+
+                serde_json::to_value(message)?
+            }
+            (&Method::GET, ["channels", channel_id, "messages", message_id]) => {
+                serde_json::to_value(
+                    guild.message(parse_channel_id(channel_id)?, parse_message_id(message_id)?)?,
+                )?
+            }
+            (&Method::PATCH, ["channels", channel_id, "messages", message_id]) => {
+                let channel_id = parse_channel_id(channel_id)?;
+                let message_id = parse_message_id(message_id)?;
+                let body = MessageBody::parse(content_type, body)?;
+                let content = body.content();
+
+                // `attachments` lists existing attachments to keep, only an empty list
+                // (replace them all) is supported
+                let message = if body.files.is_empty() && body.payload.get("attachments").is_none()
+                {
+                    guild.edit_message(channel_id, message_id, content)?
+                } else {
+                    guild.edit_message_with_files(channel_id, message_id, content, body.files)?
+                };
+
+                serde_json::to_value(message)?
+            }
+            (&Method::DELETE, ["channels", channel_id, "messages", message_id]) => {
+                guild
+                    .delete_message(parse_channel_id(channel_id)?, parse_message_id(message_id)?)?;
+
+                return Ok(None);
+            }
+            (&Method::POST, ["channels", channel_id, "messages", "bulk-delete"]) => {
+                // serenity sends the ids as numbers, Discord documents them as strings
+                let body = serde_json::from_slice::<HashMap<String, Vec<Json>>>(body)?;
+                let message_ids = body
+                    .get("messages")
+                    .into_iter()
+                    .flatten()
+                    .map(|message_id| match message_id {
+                        Json::String(message_id) => parse_message_id(message_id),
+                        message_id => parse_message_id(&message_id.to_string()),
+                    })
+                    .collect::<eyre::Result<Vec<_>>>()?;
+                guild.delete_messages(parse_channel_id(channel_id)?, &message_ids)?;
+
+                return Ok(None);
+            }
+            (
+                &Method::PUT,
+                ["channels", channel_id, "messages", message_id, "reactions", emoji, "@me"],
+            ) => {
+                let user_id = guild.current_user().id;
+                guild.react(
+                    parse_channel_id(channel_id)?,
+                    parse_message_id(message_id)?,
+                    user_id,
+                    parse_reaction(emoji)?,
+                )?;
+
+                return Ok(None);
+            }
+            (
+                &Method::DELETE,
+                ["channels", channel_id, "messages", message_id, "reactions", emoji, "@me"],
+            ) => {
+                let user_id = guild.current_user().id;
+                guild.unreact(
+                    parse_channel_id(channel_id)?,
+                    parse_message_id(message_id)?,
+                    user_id,
+                    parse_reaction(emoji)?,
+                )?;
+
+                return Ok(None);
+            }
+            (&Method::GET, ["channels", channel_id, "pins"]) => {
+                serde_json::to_value(guild.pins(parse_channel_id(channel_id)?)?)?
+            }
+            (&Method::PUT, ["channels", channel_id, "pins", message_id]) => {
+                guild.pin(parse_channel_id(channel_id)?, parse_message_id(message_id)?)?;
+
+                return Ok(None);
+            }
+            (&Method::DELETE, ["channels", channel_id, "pins", message_id]) => {
+                guild.unpin(parse_channel_id(channel_id)?, parse_message_id(message_id)?)?;
+
+                return Ok(None);
+            }
+            _ => {
+                return Err(eyre::eyre!(
+                    "unsupported route: {method} /{}",
+                    segments.join("/")
+                ))
+            }
+        };
+
+        Ok(Some(json))
+    }
+}
+
+fn is_api_version(segment: &str) -> bool {
+    matches!(segment.strip_prefix('v').map(str::parse::<u8>), Some(Ok(_)))
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+fn parse_guild_id(guild: &FakeGuild, guild_id: &str) -> eyre::Result<GuildId> {
+    let guild_id = GuildId(guild_id.parse()?);
+
+    if guild_id != guild.id() {
+        return Err(FakeApiError::UnknownGuild(guild_id).into());
+    }
+
+    Ok(guild_id)
+}
+
+fn parse_channel_id(channel_id: &str) -> eyre::Result<ChannelId> {
+    Ok(ChannelId(channel_id.parse()?))
+}
+
+fn parse_message_id(message_id: &str) -> eyre::Result<MessageId> {
+    Ok(MessageId(message_id.parse()?))
+}
+
+/// percent-encoded unicode emoji, or `name:id` of a custom emoji
+fn parse_reaction(emoji: &str) -> eyre::Result<ReactionType> {
+    let mut bytes = Vec::with_capacity(emoji.len());
+    let mut rest = emoji.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match (byte, tail) {
+            (b'%', [high, low, tail @ ..]) => {
+                let hex = std::str::from_utf8(&[*high, *low])?.to_owned();
+                bytes.push(u8::from_str_radix(&hex, 16)?);
+                rest = tail;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    let emoji = String::from_utf8(bytes)?;
+
+    let reaction = match emoji.rsplit_once(':') {
+        Some((name, id)) => ReactionType::Custom {
+            animated: false,
+            id: EmojiId(id.parse()?),
+            name: Some(name.to_owned()),
+        },
+        None => ReactionType::Unicode(emoji),
+    };
+
+    Ok(reaction)
+}
+
+/// `CreateChannel` keys are `&'static str`, so only fields the fake guild understands are kept
+fn create_channel_field(key: &str) -> Option<&'static str> {
+    [
+        "name",
+        "type",
+        "topic",
+        "nsfw",
+        "parent_id",
+        "position",
+        "rate_limit_per_user",
+        "permission_overwrites",
+    ]
+    .into_iter()
+    .find(|field| *field == key)
+}
+
+/// create/edit message request, sent as JSON or as multipart form data when files are attached
+struct MessageBody {
+    payload: Json,
+    files: Vec<MessageFile>,
+}
+
+impl MessageBody {
+    fn parse(content_type: Option<&str>, body: &[u8]) -> eyre::Result<Self> {
+        let boundary = content_type
+            .and_then(|content_type| content_type.strip_prefix("multipart/form-data"))
+            .and_then(|params| {
+                params
+                    .split(';')
+                    .find_map(|param| param.trim().strip_prefix("boundary="))
+            });
+
+        let boundary = match boundary {
+            Some(boundary) => boundary.trim_matches('"'),
+            None => {
+                return Ok(Self {
+                    payload: serde_json::from_slice(body)?,
+                    files: vec![],
+                })
+            }
+        };
+
+        let mut payload = json!({});
+        let mut files = Vec::new();
+        for part in parse_multipart(body, boundary)? {
+            match part.filename {
+                Some(filename) => files.push(MessageFile {
+                    filename,
+                    data: part.data,
+                }),
+                None if part.name == "payload_json" => {
+                    payload = serde_json::from_slice(&part.data)?
+                }
+                None => {}
+            }
+        }
+
+        Ok(Self { payload, files })
+    }
+
+    fn content(&self) -> String {
+        self.payload["content"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned()
+    }
+}
+
+struct FormPart {
+    name: String,
+    filename: Option<String>,
+    data: Vec<u8>,
+}
+
+fn parse_multipart(body: &[u8], boundary: &str) -> eyre::Result<Vec<FormPart>> {
+    let delimiter = format!("--{boundary}");
+
+    let mut parts = Vec::new();
+    for section in split_bytes(body, delimiter.as_bytes()).into_iter().skip(1) {
+        if section.starts_with(b"--") {
+            break;
+        }
+
+        let section = section.strip_prefix(b"\r\n").unwrap_or(section);
+        let section = section.strip_suffix(b"\r\n").unwrap_or(section);
+        let header_end = find_bytes(section, b"\r\n\r\n")
+            .ok_or_else(|| eyre::eyre!("invalid multipart body"))?;
+
+        let headers = std::str::from_utf8(&section[..header_end])?;
+        let disposition = headers
+            .lines()
+            .find(|line| line.to_ascii_lowercase().starts_with("content-disposition"))
+            .ok_or_else(|| eyre::eyre!("multipart part without content-disposition"))?;
+        let param = |key: &str| {
+            disposition.split(';').find_map(|param| {
+                let value = param.trim().strip_prefix(key)?.strip_prefix('=')?;
+                Some(value.trim_matches('"').to_owned())
+            })
+        };
+
+        parts.push(FormPart {
+            name: param("name").unwrap_or_default(),
+            filename: param("filename"),
+            data: section[header_end + 4..].to_vec(),
+        });
+    }
+
+    Ok(parts)
+}
+
+fn split_bytes<'a>(haystack: &'a [u8], needle: &[u8]) -> Vec<&'a [u8]> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    while let Some(position) = find_bytes(&haystack[start..], needle) {
+        sections.push(&haystack[start..start + position]);
+        start += position + needle.len();
+    }
+
+    sections.push(&haystack[start..]);
+    sections
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn json_response(status: StatusCode, json: &Json) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(json.to_string()))
+        .expect("valid response")
+}
+
+fn error_response(status: StatusCode, code: u32, message: &str) -> Response<Body> {
+    json_response(status, &json!({ "code": code, "message": message }))
+}
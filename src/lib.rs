@@ -1,5 +1,8 @@
 pub mod discord;
+#[cfg(feature = "emulator")]
+pub mod emulator;
 pub mod storage;
+pub mod transport;
 pub mod utils;
 
 pub mod debug;
@@ -0,0 +1,135 @@
+//! Row data kept in attachments of the row message.
+//!
+//! - a row over [`super::StorageOptions::attachment_threshold`] is uploaded as `row.json`,
+//!   the message content is only the stub `<attachment:row.json>`
+//! - with [`super::StorageOptions::bytea_attachments`], every `BYTEA` value is uploaded as
+//!   `bytea-{n}.bin` and replaced by `{"Attachment": "bytea-{n}.bin"}` in the row JSON
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value as Json};
+
+use crate::transport::MessageFile;
+
+pub const ROW_FILENAME: &str = "row.json";
+
+const STUB_PREFIX: &str = "<attachment:";
+const STUB_SUFFIX: &str = ">";
+
+/// how serde names `Value::Bytea` in the row JSON
+const BYTEA: &str = "Bytea";
+const ATTACHMENT: &str = "Attachment";
+
+pub fn stub(filename: &str) -> String {
+    format!("{STUB_PREFIX}{filename}{STUB_SUFFIX}")
+}
+
+/// attachment filename of a stub message, `None` for any other content
+pub fn parse_stub(content: &str) -> Option<&str> {
+    content
+        .trim()
+        .strip_prefix(STUB_PREFIX)?
+        .strip_suffix(STUB_SUFFIX)
+}
+
+/// moves every `BYTEA` value of the serialized row out into `files`
+pub fn extract_bytea(json: &mut Json, files: &mut Vec<MessageFile>) {
+    match json {
+        Json::Object(object) => {
+            let data = match object.get(BYTEA) {
+                Some(Json::Array(bytes)) if object.len() == 1 => bytes
+                    .iter()
+                    .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+                    .collect::<Vec<_>>(),
+                _ => {
+                    object
+                        .values_mut()
+                        .for_each(|value| extract_bytea(value, files));
+                    return;
+                }
+            };
+
+            let filename = format!("bytea-{}.bin", files.len());
+            *json = json!({ ATTACHMENT: filename });
+            files.push(MessageFile { filename, data });
+        }
+        Json::Array(values) => values
+            .iter_mut()
+            .for_each(|value| extract_bytea(value, files)),
+        _ => {}
+    }
+}
+
+/// filenames of the `BYTEA` placeholders in the row JSON
+pub fn referenced_files(json: &Json) -> Vec<String> {
+    match json {
+        Json::Object(object) => match object.get(ATTACHMENT) {
+            Some(Json::String(filename)) if object.len() == 1 => vec![filename.clone()],
+            _ => object.values().flat_map(referenced_files).collect(),
+        },
+        Json::Array(values) => values.iter().flat_map(referenced_files).collect(),
+        _ => vec![],
+    }
+}
+
+/// puts the downloaded `BYTEA` values back in place of their placeholders
+pub fn restore_bytea(json: &mut Json, files: &HashMap<String, Vec<u8>>) -> eyre::Result<()> {
+    match json {
+        Json::Object(object) => match object.get(ATTACHMENT) {
+            Some(Json::String(filename)) if object.len() == 1 => {
+                let data = files
+                    .get(filename)
+                    .ok_or_else(|| eyre::eyre!("missing attachment: {filename}"))?;
+
+                *json = json!({ BYTEA: data });
+                Ok(())
+            }
+            _ => object
+                .values_mut()
+                .try_for_each(|value| restore_bytea(value, files)),
+        },
+        Json::Array(values) => values
+            .iter_mut()
+            .try_for_each(|value| restore_bytea(value, files)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use gluesql_core::{prelude::Value, store::DataRow};
+
+    use super::{extract_bytea, parse_stub, referenced_files, restore_bytea, stub, ROW_FILENAME};
+
+    #[test]
+    fn stub_roundtrip() {
+        assert_eq!(parse_stub(&stub(ROW_FILENAME)), Some(ROW_FILENAME));
+        assert_eq!(parse_stub("```json\n{}\n```"), None);
+    }
+
+    #[test]
+    fn bytea_roundtrip() {
+        let row = DataRow::Vec(vec![
+            Value::I64(1),
+            Value::Bytea(vec![0, 1, 255]),
+            Value::List(vec![Value::Bytea(vec![]), Value::Str("Bytea".to_owned())]),
+        ]);
+
+        let mut json = serde_json::to_value(&row).unwrap();
+        let mut files = Vec::new();
+        extract_bytea(&mut json, &mut files);
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].data, vec![0, 1, 255]);
+        assert_eq!(referenced_files(&json), vec!["bytea-0.bin", "bytea-1.bin"]);
+
+        let files = files
+            .into_iter()
+            .map(|file| (file.filename, file.data))
+            .collect::<HashMap<_, _>>();
+        restore_bytea(&mut json, &files).unwrap();
+        assert_eq!(serde_json::from_value::<DataRow>(json).unwrap(), row);
+    }
+}
@@ -0,0 +1,77 @@
+//! Grouping of message deletes into Discord bulk delete requests.
+
+use std::ops::AddAssign;
+
+use serenity::model::prelude::MessageId;
+
+/// Discord bulk deletes 2 to 100 messages per request
+const BULK_DELETE_LIMIT: usize = 100;
+
+/// Discord only bulk deletes messages younger than 14 days, a minute is kept as slack for clock skew
+const BULK_DELETE_MAX_AGE_SECS: i64 = 14 * 24 * 60 * 60 - 60;
+
+/// How many messages were removed through each delete path.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeleteStats {
+    /// messages removed by bulk delete requests
+    pub bulk: usize,
+    /// messages removed one request each, older than 14 days or left over from a batch
+    pub single: usize,
+}
+
+impl AddAssign for DeleteStats {
+    fn add_assign(&mut self, other: Self) {
+        self.bulk += other.bulk;
+        self.single += other.single;
+    }
+}
+
+/// Bulk delete batches and the messages to delete one by one, `now` in unix seconds.
+pub fn plan(message_ids: Vec<MessageId>, now: i64) -> (Vec<Vec<MessageId>>, Vec<MessageId>) {
+    let (young, mut single): (Vec<_>, Vec<_>) = message_ids.into_iter().partition(|message_id| {
+        now - message_id.created_at().unix_timestamp() < BULK_DELETE_MAX_AGE_SECS
+    });
+
+    let mut batches = Vec::new();
+    for batch in young.chunks(BULK_DELETE_LIMIT) {
+        if batch.len() < 2 {
+            single.extend_from_slice(batch);
+        } else {
+            batches.push(batch.to_vec());
+        }
+    }
+
+    (batches, single)
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::prelude::MessageId;
+
+    use super::plan;
+    use crate::transport::DISCORD_EPOCH;
+
+    fn message_id(unix_secs: i64, sequence: u64) -> MessageId {
+        MessageId(((unix_secs as u64 * 1000 - DISCORD_EPOCH) << 22) + sequence)
+    }
+
+    #[test]
+    fn batches_young_messages() {
+        let now = 1_700_000_000;
+        let day = 24 * 60 * 60;
+
+        let young = (0..205).map(|i| message_id(now - day, i));
+        let old = (0..3).map(|i| message_id(now - 15 * day, i));
+        let (batches, single) = plan(old.clone().chain(young.clone()).collect(), now);
+
+        assert_eq!(
+            batches.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![100, 100, 5]
+        );
+        assert_eq!(single, old.collect::<Vec<_>>());
+
+        let (batches, single) = plan(young.take(101).collect(), now);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(single.len(), 1);
+    }
+}
@@ -0,0 +1,188 @@
+//! Read only columns parsed from human written messages of schemaless tables,
+//! see [`super::StorageOptions::chat_columns`]. They are parsed from the raw message content,
+//! so mentions keep their ids while the `content` column shows names.
+
+use std::collections::HashMap;
+
+use gluesql_core::prelude::Value;
+use serenity::model::prelude::MessageId;
+
+const FENCE: &str = "```";
+
+/// `mentions`, `role_mentions`, `channels`, `urls`, `code_blocks`, `emoji` and `reply_to` of a message.
+/// Everything but code blocks is only looked for outside of them.
+pub fn columns(content: &str, reply_to: Option<MessageId>) -> Vec<(String, Value)> {
+    let (text, code_blocks) = split_code_blocks(content);
+
+    let mut mentions = Vec::new();
+    let mut role_mentions = Vec::new();
+    let mut channels = Vec::new();
+    let mut emoji = Vec::new();
+    for token in angle_tokens(&text) {
+        if let Some(id) = token.strip_prefix("@&").filter(|id| is_snowflake(id)) {
+            role_mentions.push(id);
+        } else if let Some(id) = token
+            .strip_prefix("@!")
+            .or_else(|| token.strip_prefix('@'))
+            .filter(|id| is_snowflake(id))
+        {
+            mentions.push(id);
+        } else if let Some(id) = token.strip_prefix('#').filter(|id| is_snowflake(id)) {
+            channels.push(id);
+        } else if let Some((name, id)) = token
+            .strip_prefix("a:")
+            .or_else(|| token.strip_prefix(':'))
+            .and_then(|emoji| emoji.split_once(':'))
+        {
+            if !name.is_empty() && is_snowflake(id) {
+                emoji.push(format!(":{name}:"));
+            }
+        }
+    }
+    emoji.extend(text.chars().filter(is_pictograph).map(String::from));
+
+    let urls = text
+        .split_whitespace()
+        .map(|word| word.trim_start_matches(['<', '(']))
+        .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+        .map(|url| url.trim_end_matches(['>', ')', '.', ',', '!', '?']));
+
+    let code_blocks = code_blocks
+        .into_iter()
+        .map(|(language, code)| {
+            let language = language.map_or(Value::Null, |language| Value::Str(language.to_owned()));
+
+            Value::Map(HashMap::from([
+                ("language".to_owned(), language),
+                ("code".to_owned(), Value::Str(code.to_owned())),
+            ]))
+        })
+        .collect();
+
+    vec![
+        ("mentions".to_owned(), strings(mentions)),
+        ("role_mentions".to_owned(), strings(role_mentions)),
+        ("channels".to_owned(), strings(channels)),
+        ("urls".to_owned(), strings(urls)),
+        ("code_blocks".to_owned(), Value::List(code_blocks)),
+        ("emoji".to_owned(), strings(emoji)),
+        (
+            "reply_to".to_owned(),
+            reply_to.map_or(Value::Null, |message_id| Value::Str(message_id.to_string())),
+        ),
+    ]
+}
+
+fn strings<T: Into<String>>(values: impl IntoIterator<Item = T>) -> Value {
+    Value::List(
+        values
+            .into_iter()
+            .map(|value| Value::Str(value.into()))
+            .collect(),
+    )
+}
+
+/// text outside of fenced code blocks, and each block's language and code
+fn split_code_blocks(content: &str) -> (String, Vec<(Option<&str>, &str)>) {
+    let mut text = String::new();
+    let mut code_blocks = Vec::new();
+
+    let mut rest = content;
+    while let Some(start) = rest.find(FENCE) {
+        let after = &rest[start + FENCE.len()..];
+        let end = match after.find(FENCE) {
+            Some(end) => end,
+            None => break,
+        };
+
+        text.push_str(&rest[..start]);
+        text.push(' ');
+
+        let block = &after[..end];
+        let code_block = match block.split_once('\n') {
+            Some((language, code)) if !language.trim().is_empty() && !language.contains(' ') => {
+                (Some(language.trim()), code)
+            }
+            Some(("", code)) => (None, code),
+            _ => (None, block),
+        };
+        code_blocks.push(code_block);
+
+        rest = &after[end + FENCE.len()..];
+    }
+    text.push_str(rest);
+
+    (text, code_blocks)
+}
+
+/// contents of the `<…>` tokens Discord uses for mentions and custom emoji
+fn angle_tokens(text: &str) -> impl Iterator<Item = &str> {
+    text.split('<')
+        .skip(1)
+        .filter_map(|token| token.split_once('>').map(|(token, _)| token))
+}
+
+fn is_snowflake(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// emoji presentation pictographs, symbols and regional indicators
+fn is_pictograph(c: &char) -> bool {
+    matches!(*c as u32, 0x1F1E6..=0x1F1FF | 0x1F300..=0x1FAFF | 0x2600..=0x27BF)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use gluesql_core::prelude::Value;
+    use serenity::model::prelude::MessageId;
+
+    use super::columns;
+
+    fn strings(values: &[&str]) -> Value {
+        Value::List(
+            values
+                .iter()
+                .map(|value| Value::Str((*value).to_owned()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parse_chat_message() {
+        let content = "hey <@123> <@!456> <@&789>, see <#42> and <https://gluesql.org/docs>. \
+            <:glue:1001> <a:party:1002> 🎉\n```rust\nlet url = \"https://ignored.dev\"; // <@999>\n```";
+        let columns = columns(content, Some(MessageId(7)))
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(columns["mentions"], strings(&["123", "456"]));
+        assert_eq!(columns["role_mentions"], strings(&["789"]));
+        assert_eq!(columns["channels"], strings(&["42"]));
+        assert_eq!(columns["urls"], strings(&["https://gluesql.org/docs"]));
+        assert_eq!(columns["emoji"], strings(&[":glue:", ":party:", "🎉"]));
+        assert_eq!(columns["reply_to"], Value::Str("7".to_owned()));
+        assert_eq!(
+            columns["code_blocks"],
+            Value::List(vec![Value::Map(HashMap::from([
+                ("language".to_owned(), Value::Str("rust".to_owned())),
+                (
+                    "code".to_owned(),
+                    Value::Str("let url = \"https://ignored.dev\"; // <@999>\n".to_owned())
+                ),
+            ]))])
+        );
+    }
+
+    #[test]
+    fn plain_text_has_empty_columns() {
+        let columns = columns("just talking, no ``` closing fence", None)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(columns["mentions"], strings(&[]));
+        assert_eq!(columns["code_blocks"], Value::List(vec![]));
+        assert_eq!(columns["reply_to"], Value::Null);
+    }
+}
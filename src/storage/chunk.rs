@@ -0,0 +1,112 @@
+//! Rows and schemas longer than Discord's message content limit are stored as
+//! continuation messages holding the text in order, followed by a head message
+//! listing them. The head is the row message, so its id stays the row key.
+//!
+//! - continuation: `<chunk>…</chunk>`, skipped when scanning rows
+//! - head: `<chunks:{id},{id},…>`
+
+use serenity::model::prelude::MessageId;
+
+/// Discord rejects message content longer than this many characters
+pub const MESSAGE_CONTENT_LIMIT: usize = 2000;
+
+const CHUNK_PREFIX: &str = "<chunk>";
+const CHUNK_SUFFIX: &str = "</chunk>";
+const HEAD_PREFIX: &str = "<chunks:";
+const HEAD_SUFFIX: &str = ">";
+
+/// a snowflake has at most 20 digits, plus the separator
+const MAX_CHUNKS: usize =
+    (MESSAGE_CONTENT_LIMIT - HEAD_PREFIX.len() - HEAD_SUFFIX.len()) / (20 + 1);
+
+/// `None` when `text` fits in a single message, otherwise the continuation message contents
+pub fn split(text: &str) -> eyre::Result<Option<Vec<String>>> {
+    if text.chars().count() <= MESSAGE_CONTENT_LIMIT {
+        return Ok(None);
+    }
+
+    let size = MESSAGE_CONTENT_LIMIT - CHUNK_PREFIX.len() - CHUNK_SUFFIX.len();
+    let chars = text.chars().collect::<Vec<_>>();
+    let chunks = chars
+        .chunks(size)
+        .map(|chunk| format!("{CHUNK_PREFIX}{}{CHUNK_SUFFIX}", String::from_iter(chunk)))
+        .collect::<Vec<_>>();
+
+    if chunks.len() > MAX_CHUNKS {
+        eyre::bail!(
+            "content is too large: {} chunks, at most {MAX_CHUNKS}",
+            chunks.len()
+        );
+    }
+
+    Ok(Some(chunks))
+}
+
+pub fn head(chunk_ids: &[MessageId]) -> String {
+    let chunk_ids = chunk_ids
+        .iter()
+        .map(|chunk_id| chunk_id.0.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!("{HEAD_PREFIX}{chunk_ids}{HEAD_SUFFIX}")
+}
+
+/// continuation ids listed by a head message, `None` for any other content
+pub fn parse_head(content: &str) -> Option<Vec<MessageId>> {
+    content
+        .trim()
+        .strip_prefix(HEAD_PREFIX)?
+        .strip_suffix(HEAD_SUFFIX)?
+        .split(',')
+        .map(|chunk_id| chunk_id.parse().ok().map(MessageId))
+        .collect()
+}
+
+/// text held by a continuation message, `None` for any other content
+pub fn parse_chunk(content: &str) -> Option<&str> {
+    content
+        .strip_prefix(CHUNK_PREFIX)?
+        .strip_suffix(CHUNK_SUFFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::prelude::MessageId;
+
+    use super::{head, parse_chunk, parse_head, split, MAX_CHUNKS, MESSAGE_CONTENT_LIMIT};
+
+    #[test]
+    fn short_text_is_not_split() {
+        assert_eq!(split(&"a".repeat(MESSAGE_CONTENT_LIMIT)).unwrap(), None);
+    }
+
+    #[test]
+    fn chunks_roundtrip() {
+        let text = "가 ".repeat(MESSAGE_CONTENT_LIMIT);
+
+        let chunks = split(&text).unwrap().unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.chars().count() <= MESSAGE_CONTENT_LIMIT));
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| parse_chunk(chunk).unwrap())
+                .collect::<String>(),
+            text
+        );
+
+        let chunk_ids = vec![MessageId(u64::MAX); MAX_CHUNKS];
+        let head = head(&chunk_ids);
+        assert!(head.chars().count() <= MESSAGE_CONTENT_LIMIT);
+        assert_eq!(parse_head(&head), Some(chunk_ids));
+        assert_eq!(parse_head("```json\n{}\n```"), None);
+    }
+
+    #[test]
+    fn too_many_chunks() {
+        assert!(split(&"a".repeat(MESSAGE_CONTENT_LIMIT * (MAX_CHUNKS + 1))).is_err());
+    }
+}
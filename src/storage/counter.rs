@@ -0,0 +1,195 @@
+//! Reaction counters of the row messages of a table, persisted in a pinned `<counters>`
+//! message of the table channel. Its `counters.json` attachment holds the count of every
+//! emoji counted on a row, so counting never rewrites the row message.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{AttachmentId, ChannelId, MessageId};
+
+pub const HEAD: &str = "<counters>";
+pub const COUNTERS_FILENAME: &str = "counters.json";
+
+/// Counts by emoji of the row messages counted on, as stored in `counters.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CounterData {
+    rows: Vec<(MessageId, BTreeMap<String, u64>)>,
+}
+
+/// Row message → counts by emoji, per channel.
+///
+/// A channel's counters are read from its `<counters>` message and read again whenever the
+/// attachment of that message changed, so counts of other writers are picked up.
+#[derive(Default)]
+pub(crate) struct ReactionCounters {
+    channels: RefCell<HashMap<ChannelId, Counters>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// the pinned `<counters>` message and its attachment, `None` while the channel has none
+    message: Option<(MessageId, AttachmentId)>,
+    rows: HashMap<MessageId, BTreeMap<String, u64>>,
+}
+
+impl ReactionCounters {
+    /// whether the counters of the channel are loaded from `message`, the `<counters>`
+    /// message with its attachment or `None` when the channel has none
+    pub fn is_current(
+        &self,
+        channel_id: ChannelId,
+        message: Option<(MessageId, AttachmentId)>,
+    ) -> bool {
+        let channels = self.channels.borrow();
+
+        matches!(channels.get(&channel_id), Some(counters) if counters.message == message)
+    }
+
+    pub fn is_loaded(&self, channel_id: ChannelId) -> bool {
+        self.channels.borrow().contains_key(&channel_id)
+    }
+
+    /// counters read from `message`, empty for a channel without one
+    pub fn load(
+        &self,
+        channel_id: ChannelId,
+        message: Option<(MessageId, AttachmentId)>,
+        data: CounterData,
+    ) {
+        self.channels.borrow_mut().insert(
+            channel_id,
+            Counters {
+                message,
+                rows: data.rows.into_iter().collect(),
+            },
+        );
+    }
+
+    /// counts of the row message `message_id`, empty when nothing was counted on it
+    pub fn get(&self, channel_id: ChannelId, message_id: MessageId) -> BTreeMap<String, u64> {
+        let channels = self.channels.borrow();
+
+        channels
+            .get(&channel_id)
+            .and_then(|counters| counters.rows.get(&message_id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// counts `emoji` of `message_id` one up, or one down stopping at 0, and returns the
+    /// count before and after
+    pub fn count(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: String,
+        up: bool,
+    ) -> (u64, u64) {
+        let mut channels = self.channels.borrow_mut();
+        let rows = &mut channels.entry(channel_id).or_default().rows;
+        let counts = rows.entry(message_id).or_default();
+
+        let before = counts.get(&emoji).copied().unwrap_or_default();
+        let after = match up {
+            true => before + 1,
+            false => before.saturating_sub(1),
+        };
+        match after {
+            0 => counts.remove(&emoji),
+            _ => counts.insert(emoji, after),
+        };
+        if counts.is_empty() {
+            rows.remove(&message_id);
+        }
+
+        (before, after)
+    }
+
+    /// forgets the counts of deleted row messages, `false` when none of them had any
+    pub fn remove_rows(&self, channel_id: ChannelId, message_ids: &[MessageId]) -> bool {
+        let mut channels = self.channels.borrow_mut();
+        let counters = match channels.get_mut(&channel_id) {
+            Some(counters) => counters,
+            None => return false,
+        };
+
+        let mut removed = false;
+        for message_id in message_ids {
+            removed |= counters.rows.remove(message_id).is_some();
+        }
+
+        removed
+    }
+
+    pub fn remove(&self, channel_id: ChannelId) {
+        self.channels.borrow_mut().remove(&channel_id);
+    }
+
+    /// the `<counters>` message if any and the data to store in it, `None` when not loaded
+    pub fn data(&self, channel_id: ChannelId) -> Option<(Option<MessageId>, CounterData)> {
+        let channels = self.channels.borrow();
+        let counters = channels.get(&channel_id)?;
+
+        let mut rows = counters
+            .rows
+            .iter()
+            .map(|(message_id, counts)| (*message_id, counts.clone()))
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(message_id, _)| *message_id);
+
+        Some((
+            counters.message.map(|(message_id, _)| message_id),
+            CounterData { rows },
+        ))
+    }
+
+    pub fn set_message(&self, channel_id: ChannelId, message: (MessageId, AttachmentId)) {
+        if let Some(counters) = self.channels.borrow_mut().get_mut(&channel_id) {
+            counters.message = Some(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::prelude::{ChannelId, MessageId};
+
+    use super::ReactionCounters;
+
+    #[test]
+    fn counts_stop_at_zero() {
+        let counters = ReactionCounters::default();
+        let (channel_id, message_id) = (ChannelId(1), MessageId(2));
+
+        assert_eq!(
+            counters.count(channel_id, message_id, "👍".to_owned(), true),
+            (0, 1)
+        );
+        assert_eq!(
+            counters.count(channel_id, message_id, "👍".to_owned(), true),
+            (1, 2)
+        );
+        assert_eq!(counters.get(channel_id, message_id)["👍"], 2);
+
+        assert_eq!(
+            counters.count(channel_id, message_id, "👍".to_owned(), false),
+            (2, 1)
+        );
+        assert_eq!(
+            counters.count(channel_id, message_id, "👍".to_owned(), false),
+            (1, 0)
+        );
+        assert_eq!(
+            counters.count(channel_id, message_id, "👍".to_owned(), false),
+            (0, 0)
+        );
+        assert!(counters.get(channel_id, message_id).is_empty());
+
+        counters.count(channel_id, message_id, "👎".to_owned(), true);
+        assert!(counters.remove_rows(channel_id, &[MessageId(3), message_id]));
+        assert!(!counters.remove_rows(channel_id, &[message_id]));
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use serenity::{
+    client::{Context, EventHandler},
+    model::{
+        event::ChannelPinsUpdateEvent,
+        prelude::{Channel, GuildChannel, GuildId},
+    },
+};
+
+/// Channel name → channel cache, filled from a single `get_channels` call.
+#[derive(Default)]
+pub(crate) struct ChannelDirectory {
+    channels: RefCell<Option<HashMap<String, GuildChannel>>>,
+    stale: Arc<AtomicBool>,
+}
+
+impl ChannelDirectory {
+    /// `None` when the directory is not loaded, stale, or has no such channel
+    pub fn get(&self, channel_name: &str) -> Option<GuildChannel> {
+        if self.stale.load(Ordering::Acquire) {
+            return None;
+        }
+
+        self.channels.borrow().as_ref()?.get(channel_name).cloned()
+    }
+
+    /// `None` when the directory is not loaded or stale
+    pub fn all(&self) -> Option<Vec<GuildChannel>> {
+        if self.stale.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let channels = self.channels.borrow();
+        Some(channels.as_ref()?.values().cloned().collect())
+    }
+
+    pub fn replace(&self, channels: impl IntoIterator<Item = GuildChannel>) {
+        let channels = channels
+            .into_iter()
+            .map(|channel| (channel.name.clone(), channel))
+            .collect();
+
+        *self.channels.borrow_mut() = Some(channels);
+        self.stale.store(false, Ordering::Release);
+    }
+
+    pub fn insert(&self, channel: GuildChannel) {
+        if let Some(channels) = self.channels.borrow_mut().as_mut() {
+            channels.insert(channel.name.clone(), channel);
+        }
+    }
+
+    pub fn remove(&self, channel_name: &str) {
+        if let Some(channels) = self.channels.borrow_mut().as_mut() {
+            channels.remove(channel_name);
+        }
+    }
+
+    pub fn invalidate(&self) {
+        self.stale.store(true, Ordering::Release);
+    }
+
+    /// `schemas_stale` is raised on pin changes, see [`super::schema_cache::SchemaCache`]
+    pub fn events(&self, guild_id: GuildId, schemas_stale: Arc<AtomicBool>) -> ChannelEvents {
+        ChannelEvents {
+            guild_id,
+            stale: Arc::clone(&self.stale),
+            schemas_stale,
+        }
+    }
+}
+
+/// Gateway event handler that invalidates a storage's channel cache when
+/// channels of its guild are created, renamed or deleted by someone else,
+/// and its schema cache when pins of those channels change.
+///
+/// Register it on the serenity client that runs the gateway connection,
+/// e.g. `ClientBuilder::event_handler(storage.channel_events())`.
+pub struct ChannelEvents {
+    guild_id: GuildId,
+    stale: Arc<AtomicBool>,
+    schemas_stale: Arc<AtomicBool>,
+}
+
+impl ChannelEvents {
+    fn invalidate(&self, guild_id: GuildId) {
+        if guild_id == self.guild_id {
+            self.stale.store(true, Ordering::Release);
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for ChannelEvents {
+    async fn channel_create(&self, _ctx: Context, channel: &GuildChannel) {
+        self.invalidate(channel.guild_id);
+    }
+
+    async fn channel_delete(&self, _ctx: Context, channel: &GuildChannel) {
+        self.invalidate(channel.guild_id);
+    }
+
+    async fn channel_update(&self, _ctx: Context, _old: Option<Channel>, new: Channel) {
+        if let Channel::Guild(channel) = new {
+            self.invalidate(channel.guild_id);
+        }
+    }
+
+    async fn channel_pins_update(&self, _ctx: Context, pin: ChannelPinsUpdateEvent) {
+        if pin.guild_id == Some(self.guild_id) {
+            self.schemas_stale.store(true, Ordering::Release);
+        }
+    }
+}
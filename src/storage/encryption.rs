@@ -0,0 +1,115 @@
+//! Authenticated encryption of row and schema text with ChaCha20-Poly1305.
+//!
+//! Encrypted text is ` ```enc ` fenced base64 of a random 12 byte nonce followed by the
+//! ciphertext, attachment data is the same bytes without base64 or fence.
+
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+const ENCRYPTED_FENCE: &str = "```enc";
+const NONCE_LEN: usize = 12;
+
+/// 256-bit key rows are encrypted with, see [`super::StorageOptions::encryption_key`].
+#[derive(Clone)]
+pub struct EncryptionKey(Key);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key.into())
+    }
+
+    /// base64 of 32 random bytes, e.g. the output of `openssl rand -base64 32`
+    pub fn from_base64(key: &str) -> eyre::Result<Self> {
+        let key: [u8; 32] = BASE64
+            .decode(key.trim())?
+            .try_into()
+            .map_err(|key: Vec<u8>| {
+                eyre::eyre!("encryption key must be 32 bytes, got {}", key.len())
+            })?;
+
+        Ok(Self::new(key))
+    }
+
+    pub(crate) fn encrypt(&self, text: &str) -> eyre::Result<String> {
+        let data = self.encrypt_bytes(text.as_bytes())?;
+
+        Ok(format!("{ENCRYPTED_FENCE}\n{}\n```", BASE64.encode(data)))
+    }
+
+    /// `None` when `text` is not encrypted with this key, or not valid encrypted text
+    pub(crate) fn decrypt(&self, text: &str) -> Option<String> {
+        let encoded = text
+            .trim()
+            .strip_prefix(ENCRYPTED_FENCE)?
+            .strip_suffix("```")?;
+        let data = BASE64.decode(encoded.trim()).ok()?;
+
+        String::from_utf8(self.decrypt_bytes(&data)?).ok()
+    }
+
+    pub(crate) fn encrypt_bytes(&self, data: &[u8]) -> eyre::Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&self.0)
+            .encrypt(&nonce, data)
+            .map_err(|_| eyre::eyre!("failed to encrypt"))?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub(crate) fn decrypt_bytes(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        ChaCha20Poly1305::new(&self.0)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+pub fn is_encrypted(text: &str) -> bool {
+    text.trim().starts_with(ENCRYPTED_FENCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_encrypted, EncryptionKey};
+
+    #[test]
+    fn roundtrip_and_wrong_key() {
+        let key = EncryptionKey::new([7; 32]);
+
+        let encrypted = key.encrypt("```json\n{}\n```").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("{}"));
+        assert_eq!(key.decrypt(&encrypted).unwrap(), "```json\n{}\n```");
+
+        assert_eq!(EncryptionKey::new([8; 32]).decrypt(&encrypted), None);
+        assert_eq!(key.decrypt("```json\n{}\n```"), None);
+    }
+
+    #[test]
+    fn key_from_base64() {
+        let key =
+            EncryptionKey::from_base64("BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=").unwrap();
+        let encrypted = key.encrypt("glue").unwrap();
+
+        assert_eq!(
+            EncryptionKey::new([7; 32]).decrypt(&encrypted).as_deref(),
+            Some("glue")
+        );
+        assert!(EncryptionKey::from_base64("Bw==").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+use gluesql_core::prelude::Key;
+use serenity::model::prelude::MessageId;
+
+use super::gluesql;
+
+/// Errors `DiscordStorage` reports itself, boxed into [`gluesql_core::result::Error::Storage`]
+/// so callers can tell them apart with `downcast_ref::<StorageError>()`.
+#[derive(Debug)]
+pub enum StorageError {
+    /// `insert_data` was asked to update a row whose message Discord reports as unknown,
+    /// e.g. it was deleted between gluesql's scan and the update.
+    /// The row is not written again under a different key. Other failures to read the
+    /// message, such as rate limits, are returned as they are.
+    RowNotFound { table_name: String, key: Key },
+    /// The message is encrypted but `StorageOptions::encryption_key` is not set.
+    MissingEncryptionKey { message_id: MessageId },
+    /// The message is encrypted with a different key, or its ciphertext was modified.
+    Decryption { message_id: MessageId },
+    /// `COMMIT` stopped at `table_name`. Tables in `committed` were written completely,
+    /// `table_name` may be written partially and the tables after it were not written.
+    CommitFailed {
+        committed: Vec<String>,
+        table_name: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RowNotFound { table_name, key } => {
+                write!(f, "row not found: {table_name} {key:?}")
+            }
+            Self::MissingEncryptionKey { message_id } => {
+                write!(
+                    f,
+                    "message {message_id} is encrypted but no encryption key is configured"
+                )
+            }
+            Self::Decryption { message_id } => {
+                write!(
+                    f,
+                    "message {message_id} cannot be decrypted with the configured key"
+                )
+            }
+            Self::CommitFailed {
+                committed,
+                table_name,
+                reason,
+            } => write!(
+                f,
+                "commit failed at {table_name} after committing {committed:?}: {reason}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<StorageError> for gluesql::Error {
+    fn from(err: StorageError) -> Self {
+        gluesql::Error::Storage(Box::new(err))
+    }
+}
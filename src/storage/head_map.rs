@@ -0,0 +1,49 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use serenity::model::prelude::{ChannelId, MessageId};
+
+/// Row message → the continuation messages it lists, per channel.
+///
+/// A channel's map is rebuilt whenever its whole history is scanned and only kept up to
+/// date by writes in between. Only heads with continuation messages are held, so any other
+/// message of a loaded channel has none and deleting it needs no lookup.
+#[derive(Default)]
+pub(crate) struct HeadMap {
+    channels: RefCell<HashMap<ChannelId, HashMap<MessageId, Vec<MessageId>>>>,
+}
+
+impl HeadMap {
+    /// `None` when the channel is not loaded
+    pub fn get(&self, channel_id: ChannelId, message_id: MessageId) -> Option<Vec<MessageId>> {
+        let channels = self.channels.borrow();
+
+        channels
+            .get(&channel_id)
+            .map(|heads| heads.get(&message_id).cloned().unwrap_or_default())
+    }
+
+    pub fn replace(&self, channel_id: ChannelId, heads: HashMap<MessageId, Vec<MessageId>>) {
+        self.channels.borrow_mut().insert(channel_id, heads);
+    }
+
+    /// records what `message_id` lists after it was sent or edited, `chunk_ids` may be empty
+    pub fn insert(&self, channel_id: ChannelId, message_id: MessageId, chunk_ids: Vec<MessageId>) {
+        if let Some(heads) = self.channels.borrow_mut().get_mut(&channel_id) {
+            if chunk_ids.is_empty() {
+                heads.remove(&message_id);
+            } else {
+                heads.insert(message_id, chunk_ids);
+            }
+        }
+    }
+
+    pub fn remove_head(&self, channel_id: ChannelId, message_id: MessageId) {
+        if let Some(heads) = self.channels.borrow_mut().get_mut(&channel_id) {
+            heads.remove(&message_id);
+        }
+    }
+
+    pub fn remove(&self, channel_id: ChannelId) {
+        self.channels.borrow_mut().remove(&channel_id);
+    }
+}
@@ -0,0 +1,211 @@
+//! Secondary indexes, one pinned `<index:{name}>` message per index in the table channel.
+//! Its `index.json` attachment maps every indexed value to the row messages holding it.
+
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap};
+
+use gluesql_core::{
+    ast::{ColumnDef, Expr, IndexOperator},
+    prelude::Value,
+    store::DataRow,
+};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{ChannelId, MessageId};
+
+pub const INDEX_FILENAME: &str = "index.json";
+
+const HEAD_PREFIX: &str = "<index:";
+const HEAD_SUFFIX: &str = ">";
+
+pub fn head(index_name: &str) -> String {
+    format!("{HEAD_PREFIX}{index_name}{HEAD_SUFFIX}")
+}
+
+pub fn parse_head(content: &str) -> Option<&str> {
+    content.strip_prefix(HEAD_PREFIX)?.strip_suffix(HEAD_SUFFIX)
+}
+
+/// indexed column of `expr`, only plain column indexes are supported
+pub fn column_of(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Identifier(column) => Some(column),
+        _ => None,
+    }
+}
+
+/// value of `column` in `row`, `Null` when the row does not have it
+pub fn value_of(row: &DataRow, column_defs: Option<&[ColumnDef]>, column: &str) -> Value {
+    let value = match row {
+        DataRow::Vec(values) => column_defs
+            .and_then(|column_defs| column_defs.iter().position(|def| def.name == column))
+            .and_then(|position| values.get(position)),
+        DataRow::Map(values) => values.get(column),
+    };
+
+    value.cloned().unwrap_or(Value::Null)
+}
+
+/// ascending value order with `NULL`s last, values that do not compare are kept together
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        _ => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// whether `value` satisfies `value {operator} target`, `NULL` never does
+pub fn matches(value: &Value, operator: &IndexOperator, target: &Value) -> bool {
+    let ordering = match value.partial_cmp(target) {
+        Some(ordering) => ordering,
+        None => return false,
+    };
+
+    match operator {
+        IndexOperator::Gt => ordering == Ordering::Greater,
+        IndexOperator::GtEq => ordering != Ordering::Less,
+        IndexOperator::Lt => ordering == Ordering::Less,
+        IndexOperator::LtEq => ordering != Ordering::Greater,
+        IndexOperator::Eq => ordering == Ordering::Equal,
+    }
+}
+
+/// Sorts `(value, row)` pairs the way [`IndexData::scan`] orders message ids.
+pub fn sort<T>(rows: &mut [(Value, T)], asc: Option<bool>) {
+    rows.sort_by(|(a, _), (b, _)| compare(a, b));
+
+    if asc == Some(false) {
+        rows.reverse();
+    }
+}
+
+/// Indexed values with the row messages holding them, sorted by value.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IndexData {
+    entries: Vec<(Value, Vec<MessageId>)>,
+}
+
+impl IndexData {
+    pub fn insert(&mut self, value: Value, message_id: MessageId) {
+        let position = self
+            .entries
+            .binary_search_by(|(entry, _)| compare(entry, &value));
+
+        match position {
+            Ok(position) if self.entries[position].0 == value => {
+                self.entries[position].1.push(message_id)
+            }
+            Ok(position) | Err(position) => {
+                self.entries.insert(position, (value, vec![message_id]))
+            }
+        }
+    }
+
+    pub fn remove(&mut self, message_ids: &[MessageId]) {
+        if message_ids.is_empty() {
+            return;
+        }
+
+        for (_, entry_ids) in &mut self.entries {
+            entry_ids.retain(|message_id| !message_ids.contains(message_id));
+        }
+        self.entries.retain(|(_, entry_ids)| !entry_ids.is_empty());
+    }
+
+    /// message ids of the rows matching `cmp_value` in index order, descending when `asc` is `Some(false)`
+    pub fn scan(
+        &self,
+        asc: Option<bool>,
+        cmp_value: Option<(&IndexOperator, &Value)>,
+    ) -> Vec<MessageId> {
+        let entries = self.entries.iter().filter(|(value, _)| match cmp_value {
+            Some((operator, target)) => matches(value, operator, target),
+            None => true,
+        });
+
+        let message_ids = entries.flat_map(|(_, message_ids)| message_ids.iter().copied());
+        if asc == Some(false) {
+            let mut message_ids = message_ids.collect::<Vec<_>>();
+            message_ids.reverse();
+            return message_ids;
+        }
+
+        message_ids.collect()
+    }
+}
+
+/// Loaded indexes by channel and index name, with the message holding each.
+#[derive(Debug, Default)]
+pub struct IndexCache {
+    indexes: RefCell<HashMap<(ChannelId, String), (MessageId, IndexData)>>,
+}
+
+impl IndexCache {
+    pub fn get(&self, channel_id: ChannelId, index_name: &str) -> Option<(MessageId, IndexData)> {
+        self.indexes
+            .borrow()
+            .get(&(channel_id, index_name.to_owned()))
+            .cloned()
+    }
+
+    pub fn insert(
+        &self,
+        channel_id: ChannelId,
+        index_name: &str,
+        message_id: MessageId,
+        data: IndexData,
+    ) {
+        self.indexes
+            .borrow_mut()
+            .insert((channel_id, index_name.to_owned()), (message_id, data));
+    }
+
+    pub fn remove(&self, channel_id: ChannelId, index_name: &str) {
+        self.indexes
+            .borrow_mut()
+            .remove(&(channel_id, index_name.to_owned()));
+    }
+
+    pub fn remove_channel(&self, channel_id: ChannelId) {
+        self.indexes
+            .borrow_mut()
+            .retain(|(cached, _), _| *cached != channel_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql_core::{ast::IndexOperator, prelude::Value};
+    use serenity::model::prelude::MessageId;
+
+    use super::{head, parse_head, IndexData};
+
+    #[test]
+    fn head_roundtrip() {
+        assert_eq!(parse_head(&head("user_id")), Some("user_id"));
+        assert_eq!(parse_head("<chunk>user_id</chunk>"), None);
+    }
+
+    #[test]
+    fn scan_by_operator_and_order() {
+        let mut index = IndexData::default();
+        index.insert(Value::I64(2), MessageId(20));
+        index.insert(Value::Null, MessageId(40));
+        index.insert(Value::I64(1), MessageId(10));
+        index.insert(Value::I64(2), MessageId(21));
+        index.insert(Value::I64(3), MessageId(30));
+
+        assert_eq!(index.scan(None, None), [10, 20, 21, 30, 40].map(MessageId));
+        assert_eq!(
+            index.scan(Some(false), Some((&IndexOperator::GtEq, &Value::I64(2)))),
+            [30, 21, 20].map(MessageId)
+        );
+        assert_eq!(
+            index.scan(None, Some((&IndexOperator::Eq, &Value::I64(2)))),
+            [20, 21].map(MessageId)
+        );
+
+        index.remove(&[MessageId(20), MessageId(21), MessageId(40)]);
+        assert_eq!(index.scan(None, None), [10, 30].map(MessageId));
+    }
+}
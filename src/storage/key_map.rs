@@ -0,0 +1,115 @@
+//! Primary key → row message map of a primary key table, persisted in a pinned `<keys>`
+//! message of the table channel. Its `keys.json` attachment lists the primary key value of
+//! every row with the message holding it, so lookups need no scan of the channel history.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use gluesql_core::prelude::{Key, Value};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{ChannelId, MessageId};
+
+use super::gluesql;
+
+pub const HEAD: &str = "<keys>";
+pub const KEYS_FILENAME: &str = "keys.json";
+
+/// Primary key values with the row messages holding them, as stored in `keys.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeyData {
+    rows: Vec<(Value, MessageId)>,
+}
+
+/// Primary key → row message, per channel of a primary key table.
+///
+/// A channel's map is read from its `<keys>` message, or rebuilt from its messages whenever
+/// it is scanned, and kept up to date by writes in between.
+#[derive(Default)]
+pub(crate) struct KeyMap {
+    channels: RefCell<HashMap<ChannelId, Keys>>,
+}
+
+#[derive(Default)]
+struct Keys {
+    /// the pinned `<keys>` message, `None` while it is not known
+    message_id: Option<MessageId>,
+    rows: HashMap<Key, (Value, MessageId)>,
+}
+
+impl KeyMap {
+    /// `None` when the channel is not loaded
+    pub fn get(&self, channel_id: ChannelId, key: &Key) -> Option<Option<MessageId>> {
+        let channels = self.channels.borrow();
+
+        channels
+            .get(&channel_id)
+            .map(|keys| keys.rows.get(key).map(|(_, message_id)| *message_id))
+    }
+
+    pub fn is_loaded(&self, channel_id: ChannelId) -> bool {
+        self.channels.borrow().contains_key(&channel_id)
+    }
+
+    /// map read from the `<keys>` message `message_id`
+    pub fn load(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        data: KeyData,
+    ) -> gluesql::Result<()> {
+        let rows = data
+            .rows
+            .into_iter()
+            .map(|(value, row_id)| Ok(((&value).try_into()?, (value, row_id))))
+            .collect::<gluesql::Result<_>>()?;
+
+        self.channels.borrow_mut().insert(
+            channel_id,
+            Keys {
+                message_id: Some(message_id),
+                rows,
+            },
+        );
+        Ok(())
+    }
+
+    /// map rebuilt from the row messages, the known `<keys>` message is kept
+    pub fn replace(&self, channel_id: ChannelId, rows: HashMap<Key, (Value, MessageId)>) {
+        let mut channels = self.channels.borrow_mut();
+        let keys = channels.entry(channel_id).or_default();
+
+        keys.rows = rows;
+    }
+
+    pub fn insert(&self, channel_id: ChannelId, key: Key, value: Value, message_id: MessageId) {
+        if let Some(keys) = self.channels.borrow_mut().get_mut(&channel_id) {
+            keys.rows.insert(key, (value, message_id));
+        }
+    }
+
+    pub fn remove_key(&self, channel_id: ChannelId, key: &Key) {
+        if let Some(keys) = self.channels.borrow_mut().get_mut(&channel_id) {
+            keys.rows.remove(key);
+        }
+    }
+
+    pub fn remove(&self, channel_id: ChannelId) {
+        self.channels.borrow_mut().remove(&channel_id);
+    }
+
+    /// the `<keys>` message if known and the data to store in it, `None` when not loaded
+    pub fn data(&self, channel_id: ChannelId) -> Option<(Option<MessageId>, KeyData)> {
+        let channels = self.channels.borrow();
+        let keys = channels.get(&channel_id)?;
+
+        let mut rows = keys.rows.values().cloned().collect::<Vec<_>>();
+        rows.sort_by_key(|(_, message_id)| *message_id);
+
+        Some((keys.message_id, KeyData { rows }))
+    }
+
+    pub fn set_message(&self, channel_id: ChannelId, message_id: MessageId) {
+        if let Some(keys) = self.channels.borrow_mut().get_mut(&channel_id) {
+            keys.message_id = Some(message_id);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Virtual columns describing the message holding a row, see [`super::StorageOptions::metadata_columns`]
+//! and [`super::StorageOptions::reaction_columns`]. They follow the declared columns and are never
+//! stored, values written to them are dropped.
+
+use std::collections::{BTreeMap, HashMap};
+
+use gluesql_core::{
+    ast::{ColumnDef, DataType},
+    chrono::NaiveDateTime,
+    prelude::Value,
+    store::DataRow,
+};
+use serenity::model::{prelude::Message, Timestamp};
+
+/// names of the metadata columns, in the order they follow the declared ones
+pub const COLUMNS: [&str; 7] = [
+    "_id",
+    "_created_at",
+    "_edited_at",
+    "_author_id",
+    "_author_name",
+    "_pinned",
+    "_attachments",
+];
+
+const DATA_TYPES: [DataType; 7] = [
+    DataType::Text,
+    DataType::Timestamp,
+    DataType::Timestamp,
+    DataType::Text,
+    DataType::Text,
+    DataType::Boolean,
+    DataType::List,
+];
+
+/// reaction count by emoji, after the metadata columns
+pub const REACTIONS: &str = "_reactions";
+
+/// The enabled virtual columns.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VirtualColumns {
+    pub metadata: bool,
+    pub reactions: bool,
+}
+
+impl VirtualColumns {
+    pub fn any(self) -> bool {
+        self.metadata || self.reactions
+    }
+
+    /// nullable, so rows inserted without them still satisfy the schema
+    pub fn column_defs(self) -> Vec<ColumnDef> {
+        let mut columns = Vec::new();
+        if self.metadata {
+            columns.extend(COLUMNS.into_iter().zip(DATA_TYPES));
+        }
+        if self.reactions {
+            columns.push((REACTIONS, DataType::Map));
+        }
+
+        columns
+            .into_iter()
+            .map(|(name, data_type)| ColumnDef {
+                name: name.to_owned(),
+                data_type,
+                nullable: true,
+                default: None,
+                unique: None,
+            })
+            .collect()
+    }
+
+    /// `row` with the virtual columns of the message holding it, `counted` are the counts of
+    /// its reaction counters
+    pub fn append(
+        self,
+        row: DataRow,
+        message: &Message,
+        counted: &BTreeMap<String, u64>,
+    ) -> DataRow {
+        let mut columns = Vec::new();
+        if self.metadata {
+            columns.extend(COLUMNS.into_iter().zip(values(message)));
+        }
+        if self.reactions {
+            columns.push((REACTIONS, reactions(message, counted)));
+        }
+
+        match row {
+            DataRow::Vec(mut row) => {
+                row.extend(columns.into_iter().map(|(_, value)| value));
+                DataRow::Vec(row)
+            }
+            DataRow::Map(mut row) => {
+                row.extend(
+                    columns
+                        .into_iter()
+                        .map(|(name, value)| (name.to_owned(), value)),
+                );
+                DataRow::Map(row)
+            }
+        }
+    }
+}
+
+fn values(message: &Message) -> [Value; 7] {
+    let timestamp = |timestamp: Timestamp| {
+        NaiveDateTime::from_timestamp_opt(timestamp.unix_timestamp(), 0)
+            .map_or(Value::Null, Value::Timestamp)
+    };
+    let attachments = message
+        .attachments
+        .iter()
+        .map(|attachment| Value::Str(attachment.url.clone()))
+        .collect();
+
+    [
+        Value::Str(message.id.to_string()),
+        Value::Timestamp(message.id.created_at().naive_utc()),
+        message.edited_timestamp.map_or(Value::Null, timestamp),
+        Value::Str(message.author.id.to_string()),
+        Value::Str(message.author.name.clone()),
+        Value::Bool(message.pinned),
+        Value::List(attachments),
+    ]
+}
+
+/// counts keyed by the unicode emoji, or `name:id` for custom emoji. The bot's own reaction
+/// only marks a counter above 0 and is left out, the counts in `counted` are added instead.
+fn reactions(message: &Message, counted: &BTreeMap<String, u64>) -> Value {
+    let mut counts = message
+        .reactions
+        .iter()
+        .map(|reaction| {
+            (
+                reaction.reaction_type.as_data(),
+                reaction.count - u64::from(reaction.me),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    for (emoji, count) in counted {
+        *counts.entry(emoji.clone()).or_default() += count;
+    }
+
+    let counts = counts
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(emoji, count)| (emoji, Value::I64(count as i64)))
+        .collect();
+
+    Value::Map(counts)
+}
+
+/// removes the virtual columns from `row`, `columns` is the number of declared columns
+pub fn strip(row: &mut DataRow, columns: Option<usize>) {
+    match row {
+        DataRow::Vec(row) => {
+            if let Some(columns) = columns {
+                row.truncate(columns);
+            }
+        }
+        DataRow::Map(row) => {
+            row.retain(|column, _| !COLUMNS.contains(&column.as_str()) && column != REACTIONS)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql_core::{prelude::Value, store::DataRow};
+
+    use super::{strip, COLUMNS};
+
+    #[test]
+    fn strip_drops_virtual_columns() {
+        let mut row = DataRow::Vec(vec![
+            Value::I64(1),
+            Value::Str("glue".to_owned()),
+            Value::Null,
+        ]);
+        strip(&mut row, Some(2));
+        assert_eq!(
+            row,
+            DataRow::Vec(vec![Value::I64(1), Value::Str("glue".to_owned())])
+        );
+
+        let mut row = DataRow::Map(
+            [
+                ("content".to_owned(), Value::Str("hi".to_owned())),
+                (COLUMNS[0].to_owned(), Value::Str("42".to_owned())),
+            ]
+            .into(),
+        );
+        strip(&mut row, None);
+        assert_eq!(
+            row,
+            DataRow::Map([("content".to_owned(), Value::Str("hi".to_owned()))].into())
+        );
+    }
+}
@@ -1,386 +1,4752 @@
+mod attachment;
+mod bulk_delete;
+mod chat;
+mod chunk;
+mod counter;
+mod directory;
+mod encryption;
+mod error;
+mod head_map;
+mod index;
+mod key_map;
+mod metadata;
+mod options;
+mod packed;
+mod range;
+mod row_count;
+mod row_reader;
+mod scan;
+mod schema_cache;
+mod segment;
+mod transaction;
+
 mod gluesql {
     pub use gluesql_core::result::Error;
     pub use gluesql_core::result::Result;
 }
 
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
 use async_trait::async_trait;
 use gluesql_core::{
-    ast::{ColumnDef, ColumnUniqueOption},
-    chrono::Utc,
-    data::Schema,
+    ast::{ColumnDef, ColumnUniqueOption, Expr, IndexOperator, OrderByExpr},
+    chrono::{DateTime, Utc},
+    data::{Schema, SchemaIndex, SchemaIndexOrd},
+    executor::evaluate_stateless,
     prelude::{Key, Value},
-    store::{DataRow, RowIter, Store, StoreMut},
+    store::{
+        AlterTable, AlterTableError, DataRow, Index, IndexMut, RowIter, Store, StoreMut,
+        Transaction,
+    },
 };
+use serde_json::Value as Json;
 use serenity::{
-    futures::TryStreamExt,
-    model::prelude::{GuildChannel, GuildId, MessageId, MessageType},
+    builder::CreateChannel,
+    futures::{
+        future,
+        stream::{self, LocalBoxStream},
+        StreamExt, TryStreamExt,
+    },
+    model::prelude::{
+        ChannelId, GuildChannel, GuildId, Message, MessageId, MessageType, ReactionType,
+    },
+};
+
+use tokio::{
+    runtime::{self, Handle, RuntimeFlavor},
+    task,
+};
+
+use crate::{
+    debug,
+    discord::Discord,
+    transport::{is_unknown_message, MessageFile, Transport},
+    utils,
 };
 
-use crate::{debug, discord::Discord, utils};
+pub use bulk_delete::DeleteStats;
+use counter::{CounterData, ReactionCounters};
+use directory::ChannelDirectory;
+pub use directory::ChannelEvents;
+pub use encryption::EncryptionKey;
+pub use error::StorageError;
+use head_map::HeadMap;
+use index::{IndexCache, IndexData};
+use key_map::{KeyData, KeyMap};
+use metadata::VirtualColumns;
+pub use options::{RowCodec, StorageOptions};
+use range::KeyRange;
+use row_count::RowCounts;
+use row_reader::{PagedRows, RowReader};
+use schema_cache::SchemaCache;
+use segment::SegmentBuffer;
+pub use segment::{ColumnStats, SegmentStats};
+use transaction::{TableWrites, WriteSet};
+
+/// Deleting every row of a table with fewer rows is cheaper than recreating its channel,
+/// which takes about five requests.
+const TRUNCATE_MIN_ROWS: usize = 10;
 
-pub struct DiscordStorage {
-    discord: Discord,
+pub struct DiscordStorage<T: Transport = Discord> {
+    /// shared with the row iterators of `scan_data`, as are the head map, row counts and
+    /// reaction counters
+    discord: Rc<T>,
     storage_guild_id: GuildId,
+    options: StorageOptions,
+    directory: ChannelDirectory,
+    schemas: SchemaCache,
+    keys: KeyMap,
+    heads: Rc<HeadMap>,
+    row_counts: Rc<RowCounts>,
+    counters: Rc<ReactionCounters>,
+    segment_buffer: SegmentBuffer,
+    indexes: IndexCache,
+    /// writes buffered since `BEGIN`, `None` outside a transaction
+    transaction: Option<WriteSet>,
+    delete_stats: DeleteStats,
 }
 
-impl DiscordStorage {
-    pub fn new(discord: Discord, storage_guild_id: GuildId) -> Self {
+impl<T: Transport> DiscordStorage<T> {
+    pub fn new(discord: T, storage_guild_id: GuildId) -> Self {
+        Self::with_options(discord, storage_guild_id, StorageOptions::default())
+    }
+
+    pub fn with_options(discord: T, storage_guild_id: GuildId, options: StorageOptions) -> Self {
         Self {
-            discord,
+            discord: Rc::new(discord),
             storage_guild_id,
+            options,
+            directory: ChannelDirectory::default(),
+            schemas: SchemaCache::default(),
+            keys: KeyMap::default(),
+            heads: Rc::default(),
+            row_counts: Rc::default(),
+            counters: Rc::default(),
+            segment_buffer: SegmentBuffer::default(),
+            indexes: IndexCache::default(),
+            transaction: None,
+            delete_stats: DeleteStats::default(),
         }
     }
 
-    pub async fn get_schema(&self, channel: GuildChannel) -> eyre::Result<Schema> {
-        let pins = self.discord.get_pins(channel.id).await?;
-
-        let message = pins.into_iter().next();
-        let message = match message {
-            Some(msg) => msg,
-            None => {
-                return Ok(Schema {
-                    table_name: channel.name,
-                    column_defs: None,
-                    indexes: vec![],
-                    engine: None,
-                    created: Utc::now().naive_utc(),
-                })
-            }
-        };
-
-        let cache = self.discord.serenity_cache();
-        let content = message.content_safe(cache);
-
-        let schema: Schema = utils::from_discord_json(&content)?;
-        Ok(schema)
+    /// storage sharing the transport, options and scan caches of this one, owned by the row
+    /// iterator of `scan_data` so it can read after the call returns
+    fn reader(&self) -> Self {
+        Self {
+            discord: Rc::clone(&self.discord),
+            storage_guild_id: self.storage_guild_id,
+            options: self.options.clone(),
+            directory: ChannelDirectory::default(),
+            schemas: SchemaCache::default(),
+            keys: KeyMap::default(),
+            heads: Rc::clone(&self.heads),
+            row_counts: Rc::clone(&self.row_counts),
+            counters: Rc::clone(&self.counters),
+            segment_buffer: SegmentBuffer::default(),
+            indexes: IndexCache::default(),
+            transaction: None,
+            delete_stats: DeleteStats::default(),
+        }
     }
-}
 
-trait IntoStorageErr<T> {
-    fn into_storage_err(self) -> gluesql::Result<T>;
-}
-
-impl<T, E: Into<Box<dyn std::error::Error + Send + Sync>>> IntoStorageErr<T> for Result<T, E> {
-    fn into_storage_err(self) -> gluesql::Result<T> {
-        self.map_err(|err| gluesql::Error::Storage(err.into()))
+    /// Messages removed by bulk and by single deletes since the storage was created.
+    pub fn delete_stats(&self) -> DeleteStats {
+        self.delete_stats
     }
-}
-
-#[async_trait(?Send)]
-impl Store for DiscordStorage {
-    async fn fetch_schema(&self, channel_name: &str) -> gluesql::Result<Option<Schema>> {
-        debug::time!("fetch_schema", {
-            let channel_name = channel_name.to_lowercase();
-
-            let channel = self
-                .discord
-                .get_channels(self.storage_guild_id)
-                .await
-                .into_storage_err()?
-                .into_iter()
-                .find_map(|(_, channel)| {
-                    if channel.name == channel_name {
-                        Some(channel)
-                    } else {
-                        None
-                    }
-                });
 
-            match channel {
-                Some(channel) => self.get_schema(channel).await.into_storage_err().map(Some),
-                None => Ok(None),
+    /// Uploads the rows appended in segment mode that do not fill a segment yet, as one
+    /// shorter segment per table. Scans of a table upload its rows first, and `COMMIT` and
+    /// dropping the storage upload every table's.
+    pub async fn flush(&self) -> gluesql::Result<()> {
+        debug::time!("flush", {
+            for channel_id in self.segment_buffer.channel_ids() {
+                self.flush_segments(channel_id).await.into_storage_err()?;
             }
+
+            Ok(())
         })
     }
 
-    async fn fetch_all_schemas(&self) -> gluesql::Result<Vec<Schema>> {
-        debug::time!("fetch_all_schemas", {
-            let channels = self
-                .discord
-                .get_channels(self.storage_guild_id)
+    /// Statistics of the segment messages of `table_name`, oldest first, read from the message
+    /// content alone so a filtered scan can tell which segments to skip before downloading them.
+    pub async fn segment_stats(&self, table_name: &str) -> gluesql::Result<Vec<SegmentStats>> {
+        debug::time!("segment_stats", {
+            let channel_id = self
+                .channel_id(&table_name.to_lowercase())
                 .await
-                .into_storage_err()?;
+                .into_storage_err()?
+                .ok_or_else(|| {
+                    gluesql::Error::Storage("segment_stats) not found channel".into())
+                })?;
+            self.flush_segments(channel_id).await.into_storage_err()?;
 
-            let mut schemas = Vec::new();
-            for (_channel_id, channel) in channels {
-                let schema = self.get_schema(channel).await.into_storage_err()?;
-                schemas.push(schema);
+            let mut messages = self.discord.oldest_message_stream(channel_id, MessageId(0));
+            let mut stats = Vec::new();
+            while let Some(message) = messages.try_next().await.into_storage_err()? {
+                if !matches!(message.kind, MessageType::Regular)
+                    || message.pinned
+                    || chunk::parse_chunk(&self.content(&message)).is_some()
+                {
+                    continue;
+                }
+
+                let text = self.message_text(&message).await.into_storage_err()?;
+                stats.extend(
+                    self.decode_segment_stats(message.id, &text)
+                        .into_storage_err()?,
+                );
             }
 
-            Ok(schemas)
+            Ok(stats)
         })
     }
 
-    async fn fetch_data(&self, channel_name: &str, key: &Key) -> gluesql::Result<Option<DataRow>> {
-        debug::time!("fetch_data", {
-            let channel_name = channel_name.to_lowercase();
-            let message_id: u64 = match key {
-                Key::Str(id) => id
-                    .parse()
-                    .map_err(|err| gluesql::Error::Storage(format!("invalid key: {err}").into()))?,
-                _ => return Err(gluesql::Error::Storage("invalid key".into())),
-            };
-            let message_id = MessageId(message_id);
+    /// Rows of `table_name` as the history is read, oldest first one page at a time while the
+    /// stream is polled, so dropping it early stops the download. Rows of tables with a primary
+    /// key come sorted by key and rows with writes buffered in a transaction come merged, both
+    /// are read whole into memory before the first one is yielded.
+    pub async fn scan_stream(
+        &self,
+        table_name: &str,
+    ) -> gluesql::Result<LocalBoxStream<'_, gluesql::Result<(Key, DataRow)>>> {
+        if let Some(channel_id) = self.paged_channel(table_name).await? {
+            let rows = self
+                .row_stream(channel_id, KeyRange::default(), None)
+                .map(|row| {
+                    row.map(|(message, slot, row)| {
+                        (row_key(message.id, slot), self.with_metadata(&message, row))
+                    })
+                    .into_storage_err()
+                });
 
-            let channel_id = self
-                .discord
-                .get_channel_id(self.storage_guild_id, channel_name)
+            return Ok(rows.boxed_local());
+        }
+
+        let channel_name = table_name.to_lowercase();
+        let channel = self
+            .channel(&channel_name)
+            .await
+            .into_storage_err()?
+            .ok_or_else(|| gluesql::Error::Storage("scan_data) not found channel".into()))?;
+        let channel_id = channel.id;
+
+        let primary_key = self.primary_key(channel).await?;
+        let writes = self.buffered(&channel_name);
+        let rows = match primary_key {
+            Some(primary_key) => self.primary_key_rows(channel_id, primary_key).await?,
+            None => self
+                .row_messages(channel_id)
                 .await
                 .into_storage_err()?
-                .ok_or_else(|| gluesql::Error::Storage("fetch_data) not found channel".into()))?;
+                .into_iter()
+                .map(|(message, slot, row)| {
+                    (row_key(message.id, slot), self.with_metadata(&message, row))
+                })
+                .collect(),
+        };
+        let rows = match writes {
+            Some(writes) => writes.apply(rows, primary_key.is_some()),
+            None => rows,
+        };
 
-            let message = self.discord.get_message(channel_id, message_id).await.ok();
-            let message = match message {
-                Some(message) => message,
-                None => return Ok(None),
-            };
+        Ok(stream::iter(rows.into_iter().map(Ok)).boxed_local())
+    }
 
-            let cache = self.discord.serenity_cache();
-            let content = message.content_safe(cache);
+    /// Channel of `table_name` when its rows can be read as the history arrives, `None` when
+    /// every row is needed first: rows of primary key tables are sorted by key and writes
+    /// buffered in a transaction are merged. Buffered segment rows are uploaded and reaction
+    /// counters read first.
+    async fn paged_channel(&self, table_name: &str) -> gluesql::Result<Option<ChannelId>> {
+        let channel_name = table_name.to_lowercase();
+        let channel = self
+            .channel(&channel_name)
+            .await
+            .into_storage_err()?
+            .ok_or_else(|| gluesql::Error::Storage("scan_data) not found channel".into()))?;
+        let channel_id = channel.id;
+        self.flush_segments(channel_id).await.into_storage_err()?;
+        self.read_counters(channel_id).await?;
 
-            let row: DataRow = utils::from_discord_json(&content).unwrap_or(DataRow::Map(
-                [("content".to_owned(), Value::Str(content))].into(),
-            ));
-            Ok(Some(row))
-        })
+        let paged =
+            self.primary_key(channel).await?.is_none() && self.buffered(&channel_name).is_none();
+
+        Ok(paged.then_some(channel_id))
     }
 
-    async fn scan_data(&self, channel_name: &str) -> gluesql::Result<RowIter> {
-        debug::time!("scan_data", {
-            let channel_name = channel_name.to_lowercase();
-            let channel_id = self
-                .discord
-                .get_channel_id(self.storage_guild_id, channel_name)
-                .await
-                .into_storage_err()?
-                .ok_or_else(|| gluesql::Error::Storage("scan_data) not found channel".into()))?;
+    /// Rows of `table_name` with keys from `from_key` inclusive to `to_key` exclusive, `None`
+    /// leaves that side open. History is read only from `from_key` on and no further than
+    /// `to_key`. Only tables without a primary key are keyed by message id, rows appended
+    /// in an open transaction have no message id yet and are left out.
+    pub async fn scan_range(
+        &self,
+        table_name: &str,
+        from_key: Option<&Key>,
+        to_key: Option<&Key>,
+    ) -> gluesql::Result<Vec<(Key, DataRow)>> {
+        let range = KeyRange::new(from_key, to_key).map_err(|key| {
+            gluesql::Error::Storage(format!("scan_range) invalid key: {key:?}").into())
+        })?;
 
-            let messages = self
-                .discord
-                .latest_message_stream(channel_id)
-                .map_ok(|message| {
-                    let message = match message.kind {
-                        MessageType::Regular if !message.pinned => message,
-                        _ => return Ok(None),
-                    };
+        self.range_rows(table_name, range, None).await
+    }
 
-                    let cache = self.discord.serenity_cache();
-                    let content = message.content_safe(cache);
+    /// Rows of `table_name` sent from `from` inclusive to `to` exclusive, read like
+    /// [`DiscordStorage::scan_range`] since message ids begin with their send time.
+    pub async fn scan_time_range(
+        &self,
+        table_name: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> gluesql::Result<Vec<(Key, DataRow)>> {
+        self.range_rows(table_name, KeyRange::between(from, to), None)
+            .await
+    }
 
-                    let row: DataRow = utils::from_discord_json(&content).unwrap_or(DataRow::Map(
-                        [("content".to_owned(), Value::Str(content))].into(),
-                    ));
-                    let key = Key::Str(message.id.0.to_string());
+    /// Rows of `table_name` whose `column` compares to `value` by `operator`, read like
+    /// [`DiscordStorage::scan_range`] over the whole history. Segment messages whose
+    /// [`SegmentStats`] rule the comparison out are skipped without downloading their rows.
+    pub async fn scan_filtered(
+        &self,
+        table_name: &str,
+        column: &str,
+        operator: &IndexOperator,
+        value: &Value,
+    ) -> gluesql::Result<Vec<(Key, DataRow)>> {
+        self.range_rows(
+            table_name,
+            KeyRange::default(),
+            Some((column, operator, value)),
+        )
+        .await
+    }
 
-                    Ok(Some((key, row)))
-                })
-                .try_collect::<Vec<_>>()
+    /// Counts the row `key` one up under `reaction` and returns the new count, read back from
+    /// the `_reactions` column of [`StorageOptions::reaction_columns`] added to the reactions
+    /// of users. Counts are kept in the table's pinned `<counters>` message, so the row message
+    /// is not rewritten, and the bot reacts to the row message while its count is above 0.
+    /// Rows of packed and segment messages share their message and can't be counted on. The
+    /// count is written right away, also inside a transaction.
+    pub async fn increment_reaction(
+        &self,
+        table_name: &str,
+        key: &Key,
+        reaction: impl Into<ReactionType>,
+    ) -> gluesql::Result<u64> {
+        debug::time!("increment_reaction", {
+            self.count_reaction(table_name, key, reaction.into(), true)
                 .await
-                .into_storage_err()?;
+        })
+    }
 
-            Ok(Box::new(messages.into_iter().filter_map(|row| row.transpose()).rev()) as RowIter)
+    /// Counts the row `key` one down under `reaction` and returns the new count, a count of 0
+    /// stays 0. The bot's reaction is removed once the count is back to 0, reactions of users
+    /// are left alone.
+    pub async fn decrement_reaction(
+        &self,
+        table_name: &str,
+        key: &Key,
+        reaction: impl Into<ReactionType>,
+    ) -> gluesql::Result<u64> {
+        debug::time!("decrement_reaction", {
+            self.count_reaction(table_name, key, reaction.into(), false)
+                .await
         })
     }
-}
 
-#[async_trait(?Send)]
-impl StoreMut for DiscordStorage {
-    async fn insert_schema(&mut self, schema: &Schema) -> gluesql::Result<()> {
-        debug::time!("insert_schema", {
-            if schema.column_defs.iter().any(|column_def| {
-                column_def.iter().any(|ColumnDef { unique, .. }| {
-                    matches!(unique, Some(ColumnUniqueOption { is_primary: true }))
-                })
-            }) {
-                return Err(gluesql::Error::Storage(
-                    "primary key is not supported".into(),
-                ));
-            }
+    async fn count_reaction(
+        &self,
+        table_name: &str,
+        key: &Key,
+        reaction: ReactionType,
+        up: bool,
+    ) -> gluesql::Result<u64> {
+        let (channel_id, message_id) = self.reaction_message(table_name, key).await?;
+        self.load_counters(channel_id).await.into_storage_err()?;
 
-            let channel_name = &schema.table_name.to_lowercase();
+        let (before, after) = self
+            .counters
+            .count(channel_id, message_id, reaction.as_data(), up);
+        if before == after {
+            return Ok(after);
+        }
+        self.save_counters(channel_id).await.into_storage_err()?;
 
-            let channel_id = self
+        match (before, after) {
+            (0, _) => self
                 .discord
-                .get_channel_id(self.storage_guild_id, channel_name)
+                .create_reaction(channel_id, message_id, reaction)
                 .await
-                .into_storage_err()?;
+                .into_storage_err()?,
+            (_, 0) => self
+                .discord
+                .delete_reaction(channel_id, message_id, reaction)
+                .await
+                .into_storage_err()?,
+            _ => {}
+        }
 
-            let channel_id = match channel_id {
-                Some(channel_id) => channel_id,
-                None => {
-                    let channel = self
-                        .discord
-                        .create_channel(self.storage_guild_id, |f| f.name(&schema.table_name))
-                        .await
-                        .into_storage_err()?;
+        Ok(after)
+    }
 
-                    channel.id
-                }
-            };
+    async fn reaction_message(
+        &self,
+        table_name: &str,
+        key: &Key,
+    ) -> gluesql::Result<(ChannelId, MessageId)> {
+        let channel_name = table_name.to_lowercase();
+        let channel = self
+            .channel(&channel_name)
+            .await
+            .into_storage_err()?
+            .ok_or_else(|| gluesql::Error::Storage("reaction) not found channel".into()))?;
+        let channel_id = channel.id;
 
-            let pin_messages = self.discord.get_pins(channel_id).await.into_storage_err()?;
+        let row_not_found = || StorageError::RowNotFound {
+            table_name: channel_name.clone(),
+            key: key.clone(),
+        };
+        let message_id = match self.row_position(channel, key).await? {
+            Some((message_id, None)) => message_id,
+            Some((_, Some(_))) => {
+                return Err(gluesql::Error::Storage(
+                    "reaction) rows of packed and segment messages can't be counted on".into(),
+                ))
+            }
+            None => return Err(row_not_found().into()),
+        };
+        match self.discord.get_message(channel_id, message_id).await {
+            Ok(_) => Ok((channel_id, message_id)),
+            Err(err) if is_unknown_message(&err) => Err(row_not_found().into()),
+            Err(err) => Err(gluesql::Error::Storage(err.into())),
+        }
+    }
 
-            if !pin_messages.is_empty() {
+    /// rows in `range` that satisfy `filter`, `column {operator} value`
+    async fn range_rows(
+        &self,
+        table_name: &str,
+        range: KeyRange,
+        filter: Option<(&str, &IndexOperator, &Value)>,
+    ) -> gluesql::Result<Vec<(Key, DataRow)>> {
+        debug::time!("scan_range", {
+            let channel_name = table_name.to_lowercase();
+            let channel = self
+                .channel(&channel_name)
+                .await
+                .into_storage_err()?
+                .ok_or_else(|| gluesql::Error::Storage("scan_range) not found channel".into()))?;
+            let channel_id = channel.id;
+
+            if self.primary_key(channel.clone()).await?.is_some() {
                 return Err(gluesql::Error::Storage(
-                    format!("channel is already pinned: {channel_name}").into(),
+                    "scan_range) table has a primary key".into(),
                 ));
             }
+            self.flush_segments(channel_id).await.into_storage_err()?;
+            self.read_counters(channel_id).await?;
 
-            let content = utils::to_discord_json(&schema).into_storage_err()?;
+            let filter = match filter {
+                Some((column, operator, value)) => {
+                    let schema = self.schema(channel).await.into_storage_err()?;
+                    let column = schema
+                        .column_defs
+                        .unwrap_or_default()
+                        .iter()
+                        .position(|column_def| column_def.name == column)
+                        .ok_or_else(|| {
+                            gluesql::Error::Storage(
+                                format!("scan_range) not found column: {column}").into(),
+                            )
+                        })?;
 
-            let message = self
-                .discord
-                .send_message(channel_id, content)
-                .await
-                .into_storage_err()?;
+                    Some(ColumnFilter {
+                        column,
+                        operator,
+                        value,
+                    })
+                }
+                None => None,
+            };
 
-            self.discord
-                .set_pin(channel_id, message.id)
+            let rows = self
+                .row_stream(channel_id, range, filter)
+                .map_ok(|(message, slot, row)| {
+                    (row_key(message.id, slot), self.with_metadata(&message, row))
+                })
+                .try_collect::<Vec<_>>()
                 .await
                 .into_storage_err()?;
+            let rows = match self.buffered(&channel_name) {
+                Some(writes) => writes
+                    .apply(rows, false)
+                    .into_iter()
+                    .filter(|(key, row)| {
+                        matches!(key, Key::Str(id) if packed::parse_key(id).is_some())
+                            && filter.iter().all(|filter| filter.matches(row))
+                    })
+                    .collect(),
+                None => rows,
+            };
 
-            Ok(())
+            Ok(rows)
         })
     }
 
-    async fn delete_schema(&mut self, channel_name: &str) -> gluesql::Result<()> {
-        debug::time!("delete_schema", {
-            let channel_name = &channel_name.to_lowercase();
-
-            let channel_id = self
-                .discord
-                .get_channel_id(self.storage_guild_id, channel_name)
+    /// Removes every row of `table_name`. A table with declared columns and no index is
+    /// replaced by an empty channel with the same name, position, settings and schema,
+    /// so **the table gets a new channel id**. Schemaless and indexed tables keep their channel
+    /// and have their rows deleted like any other `DELETE`, as do tables in a transaction,
+    /// whose rows are deleted on `COMMIT`. A `DELETE` of every row of a table with at least
+    /// ten rows recreates its channel the same way.
+    pub async fn truncate_table(&mut self, table_name: &str) -> gluesql::Result<()> {
+        debug::time!("truncate_table", {
+            let channel_name = table_name.to_lowercase();
+            let channel = self
+                .channel(&channel_name)
                 .await
-                .into_storage_err()?;
-            let channel_id = channel_id.ok_or_else(|| {
-                gluesql::Error::Storage("delete_schema) not found channel".into())
-            })?;
+                .into_storage_err()?
+                .ok_or_else(|| {
+                    gluesql::Error::Storage("truncate_table) not found channel".into())
+                })?;
 
-            self.discord
-                .delete_channel(channel_id)
-                .await
-                .into_storage_err()?;
+            let schema = self.schema(channel.clone()).await.into_storage_err()?;
+            if self.transaction.is_some() || !is_recreatable(&schema) {
+                let keys = self
+                    .scan_data(table_name)
+                    .await?
+                    .map(|row| row.map(|(key, _)| key))
+                    .collect::<gluesql::Result<Vec<_>>>()?;
 
-            Ok(())
+                return self.delete_data(table_name, keys).await;
+            }
+
+            tracing::debug!("truncate_table) recreating {channel_name}");
+            self.recreate_channel(channel).await.into_storage_err()
         })
     }
 
-    async fn append_data(&mut self, channel_name: &str, rows: Vec<DataRow>) -> gluesql::Result<()> {
-        debug::time!("append_data", {
-            let storage = self;
-            let channel_name = &channel_name.to_lowercase();
+    /// Forgets every cached channel, the next lookup lists the guild channels again.
+    pub fn invalidate_channel_cache(&self) {
+        self.directory.invalidate();
+    }
 
-            let channel_id = storage
-                .discord
-                .get_channel_id(storage.storage_guild_id, channel_name)
-                .await
-                .into_storage_err()?;
-            let channel_id = channel_id
-                .ok_or_else(|| gluesql::Error::Storage("append_data) not found channel".into()))?;
+    /// Forgets every parsed schema, the next lookup reads the pinned schema messages again.
+    pub fn invalidate_schema_cache(&self) {
+        self.schemas.invalidate();
+    }
 
-            for row in rows {
-                let content = utils::to_discord_json(&row).into_storage_err()?;
+    /// Gateway event handler keeping the channel and schema caches in sync with changes made elsewhere.
+    pub fn channel_events(&self) -> ChannelEvents {
+        self.directory
+            .events(self.storage_guild_id, self.schemas.stale_flag())
+    }
 
-                storage
-                    .discord
-                    .send_message(channel_id, content)
-                    .await
-                    .into_storage_err()?;
-            }
+    async fn refresh_channels(&self) -> eyre::Result<Vec<GuildChannel>> {
+        let channels = self
+            .discord
+            .get_channels(self.storage_guild_id)
+            .await?
+            .into_values()
+            .collect::<Vec<_>>();
 
-            Ok(())
-        })
+        self.directory.replace(channels.clone());
+        Ok(channels)
     }
 
-    async fn insert_data(
-        &mut self,
-        channel_name: &str,
-        rows: Vec<(Key, DataRow)>,
-    ) -> gluesql::Result<()> {
-        debug::time!("insert_data", {
-            let channel_name = &channel_name.to_lowercase();
+    async fn channels(&self) -> eyre::Result<Vec<GuildChannel>> {
+        match self.directory.all() {
+            Some(channels) => Ok(channels),
+            None => self.refresh_channels().await,
+        }
+    }
 
-            let channel_id = self
-                .discord
-                .get_channel_id(self.storage_guild_id, channel_name)
-                .await
-                .into_storage_err()?;
-            let channel_id = channel_id
-                .ok_or_else(|| gluesql::Error::Storage("insert_data) not found channel".into()))?;
+    /// a cache miss lists the guild channels again before giving up
+    async fn channel(&self, channel_name: &str) -> eyre::Result<Option<GuildChannel>> {
+        if let Some(channel) = self.directory.get(channel_name) {
+            return Ok(Some(channel));
+        }
 
-            for row in rows {
-                let (key, row) = row;
+        self.refresh_channels().await?;
+        Ok(self.directory.get(channel_name))
+    }
 
-                let key = match key {
-                    Key::Str(key) => key,
-                    _ => {
-                        return Err(gluesql::Error::Storage(
-                            eyre::eyre!("invalid key {key:?}").into(),
-                        ))
-                    }
-                };
+    async fn channel_id(&self, channel_name: &str) -> eyre::Result<Option<ChannelId>> {
+        Ok(self.channel(channel_name).await?.map(|channel| channel.id))
+    }
 
-                let message_id = MessageId(key.parse().map_err(|_| {
-                    gluesql::Error::Storage("insert_data) failed key parsing".into())
-                })?);
+    /// cached [`Self::get_schema`], reused while the channel's `last_pin_timestamp` is unchanged
+    async fn schema(&self, channel: GuildChannel) -> eyre::Result<Schema> {
+        if let Some(schema) = self.schemas.get(&channel) {
+            return Ok(schema);
+        }
 
-                let content = utils::to_discord_json(&row).into_storage_err()?;
+        let schema = self.get_schema(channel.clone()).await?;
+        self.schemas.insert(&channel, schema.clone());
 
-                let message = self.discord.get_message(channel_id, message_id).await.ok();
+        Ok(schema)
+    }
 
-                match message {
-                    Some(_) => {
-                        self.discord
-                            .edit_message(channel_id, message_id, content)
-                            .await
-                            .into_storage_err()?;
-                    }
-                    None => {
-                        self.discord
-                            .send_message(channel_id, content)
-                            .await
-                            .into_storage_err()?;
-                    }
-                }
-            }
+    /// column position of the primary key, `None` for tables without one
+    async fn primary_key(&self, channel: GuildChannel) -> gluesql::Result<Option<usize>> {
+        let schema = self.schema(channel).await.into_storage_err()?;
 
-            Ok(())
-        })
+        Ok(schema.column_defs.and_then(|column_defs| {
+            column_defs.iter().position(|ColumnDef { unique, .. }| {
+                matches!(unique, Some(ColumnUniqueOption { is_primary: true }))
+            })
+        }))
     }
 
-    async fn delete_data(&mut self, channel_name: &str, keys: Vec<Key>) -> gluesql::Result<()> {
-        debug::time!("delete_data", {
-            let channel_name = &channel_name.to_lowercase();
+    fn content(&self, message: &Message) -> String {
+        let cache = self.discord.serenity_cache();
 
-            let channel_id = self
+        message.content_safe(cache)
+    }
+
+    /// content of `message`, joining the continuation messages back when it is a chunked head
+    async fn message_text(&self, message: &Message) -> eyre::Result<String> {
+        let content = self.content(message);
+        let chunk_ids = match chunk::parse_head(&content) {
+            Some(chunk_ids) => chunk_ids,
+            None => return Ok(content),
+        };
+
+        let mut text = String::new();
+        for chunk_id in chunk_ids {
+            let chunk = self
                 .discord
-                .get_channel_id(self.storage_guild_id, channel_name)
-                .await
-                .into_storage_err()?;
-            let channel_id = channel_id
-                .ok_or_else(|| gluesql::Error::Storage("delete_data) not found channel".into()))?;
+                .get_message(message.channel_id, chunk_id)
+                .await?;
+            let content = self.content(&chunk);
 
-            for key in keys {
-                let key = match key {
-                    Key::Str(key) => key,
-                    _ => {
-                        return Err(gluesql::Error::Storage(
-                            eyre::eyre!("invalid key {key:?}").into(),
-                        ))
-                    }
-                };
+            text.push_str(
+                chunk::parse_chunk(&content)
+                    .ok_or_else(|| eyre::eyre!("not a chunk message: {chunk_id}"))?,
+            );
+        }
 
-                let message_id = MessageId(key.parse().map_err(|_| {
-                    gluesql::Error::Storage("delete_data) failed key parsing".into())
-                })?);
+        Ok(text)
+    }
 
-                self.discord
-                    .delete_message(channel_id, message_id)
-                    .await
-                    .into_storage_err()?;
-            }
+    /// rows of the channel with the message holding them, oldest first,
+    /// rows of packed messages come with their slot, buffered segment rows are uploaded first
+    async fn row_messages(
+        &self,
+        channel_id: ChannelId,
+    ) -> eyre::Result<Vec<(Message, Option<usize>, DataRow)>> {
+        self.flush_segments(channel_id).await?;
 
-            Ok(())
+        self.row_stream(channel_id, KeyRange::default(), None)
+            .try_collect()
+            .await
+    }
+
+    /// [`Self::row_messages`] in `range` that satisfy `filter` as a stream, history is read one
+    /// page at a time as it is polled and no further than the end of the range. Segments the
+    /// filter rules out are not downloaded. Reading the whole history unfiltered rebuilds the
+    /// channel's head map and counts its rows.
+    fn row_stream<'a>(
+        &'a self,
+        channel_id: ChannelId,
+        range: KeyRange,
+        filter: Option<ColumnFilter<'a>>,
+    ) -> LocalBoxStream<'a, eyre::Result<(Message, Option<usize>, DataRow)>> {
+        let reader = RowReader::new(channel_id, range, filter);
+
+        stream::try_unfold(reader, move |mut reader| async move {
+            let row = reader.next(self).await?;
+
+            Ok::<_, eyre::Report>(row.map(|row| (row, reader)))
         })
+        .boxed_local()
+    }
+
+    /// Rows of `message`, a head whose continuation messages were not read yet is joined first.
+    /// A message written by a person that carries a chunk, encryption, attachment or packing
+    /// marker but does not decode is read as plain text, like one that is not valid JSON.
+    async fn decode_message(
+        &self,
+        message: Message,
+        text: String,
+    ) -> eyre::Result<Vec<(Message, Option<usize>, DataRow)>> {
+        let decoded = match chunk::parse_head(&text) {
+            Some(_) => match self.message_text(&message).await {
+                Ok(text) => self.decode_rows(message.clone(), text).await,
+                Err(err) => Err(err),
+            },
+            None => self.decode_rows(message.clone(), text).await,
+        };
+
+        match decoded {
+            Err(_) if !is_row_message(&message) => {
+                let row = self.text_row(&message, self.content(&message));
+
+                Ok(vec![(message, None, row)])
+            }
+            decoded => decoded,
+        }
+    }
+
+    /// rows held by a row message with its full text, a single row or the filled slots
+    async fn decode_rows(
+        &self,
+        message: Message,
+        text: String,
+    ) -> eyre::Result<Vec<(Message, Option<usize>, DataRow)>> {
+        if let Some(slots) = self.decode_slots(&message, &text).await? {
+            let rows = slots
+                .into_iter()
+                .enumerate()
+                .filter_map(|(slot, row)| Some((message.clone(), Some(slot), row?)))
+                .collect();
+
+            return Ok(rows);
+        }
+
+        let row = self.decode_row(&message, text).await?;
+
+        Ok(vec![(message, None, row)])
+    }
+
+    fn virtual_columns(&self) -> VirtualColumns {
+        VirtualColumns {
+            metadata: self.options.metadata_columns,
+            reactions: self.options.reaction_columns,
+        }
+    }
+
+    /// `row` with the metadata and reaction columns of `message` that are enabled, reaction
+    /// counts are taken from the counters loaded by [`DiscordStorage::read_counters`]
+    fn with_metadata(&self, message: &Message, row: DataRow) -> DataRow {
+        match self.virtual_columns() {
+            columns if columns.any() => {
+                let counted = self.counters.get(message.channel_id, message.id);
+
+                columns.append(row, message, &counted)
+            }
+            _ => row,
+        }
+    }
+
+    /// `schema` as gluesql sees it, declared columns are followed by the enabled virtual columns
+    fn with_metadata_columns(&self, mut schema: Schema) -> Schema {
+        if let Some(column_defs) = schema.column_defs.as_mut() {
+            column_defs.extend(self.virtual_columns().column_defs());
+        }
+
+        schema
+    }
+
+    /// number of declared columns, `None` for schemaless tables
+    async fn declared_columns(&self, channel: GuildChannel) -> gluesql::Result<Option<usize>> {
+        let schema = self.schema(channel).await.into_storage_err()?;
+
+        Ok(schema.column_defs.map(|column_defs| column_defs.len()))
+    }
+
+    /// message content and attachments holding `row`
+    fn encode_row(&self, row: &DataRow) -> eyre::Result<(String, Vec<MessageFile>)> {
+        let mut json = serde_json::to_value(row)?;
+        let mut files = Vec::new();
+        if self.options.bytea_attachments {
+            attachment::extract_bytea(&mut json, &mut files);
+        }
+
+        let text = self.options.row_codec.encode(&json)?;
+        let text = match &self.options.encryption_key {
+            Some(key) => {
+                for file in &mut files {
+                    file.data = key.encrypt_bytes(&file.data)?;
+                }
+
+                key.encrypt(&text)?
+            }
+            None => text,
+        };
+        match self.options.attachment_threshold {
+            Some(threshold) if text.chars().count() > threshold => {
+                files.push(MessageFile {
+                    filename: attachment::ROW_FILENAME.to_owned(),
+                    data: text.into_bytes(),
+                });
+
+                Ok((attachment::stub(attachment::ROW_FILENAME), files))
+            }
+            _ => Ok((text, files)),
+        }
+    }
+
+    /// text of a packed message holding `slots`, `BYTEA` values stay inline
+    fn encode_pack(&self, slots: &[Option<DataRow>]) -> eyre::Result<String> {
+        let text = self.options.row_codec.encode(&slots)?;
+        let text = match &self.options.encryption_key {
+            Some(key) => key.encrypt(&text)?,
+            None => text,
+        };
+
+        Ok(packed::content(&text))
+    }
+
+    /// slots of a packed message with text `text`, `None` when it holds a single row
+    fn decode_pack(
+        &self,
+        message_id: MessageId,
+        text: &str,
+    ) -> eyre::Result<Option<Vec<Option<DataRow>>>> {
+        let text = match packed::parse(text) {
+            Some(text) => text.to_owned(),
+            None => return Ok(None),
+        };
+
+        let (text, _) = self.decrypt(message_id, text)?;
+        utils::from_discord_json(&text).map(Some)
+    }
+
+    /// content and `segment.json` attachment of a segment message holding `slots`
+    fn encode_segment(
+        &self,
+        slots: &[Option<DataRow>],
+    ) -> eyre::Result<(String, Vec<MessageFile>)> {
+        let stats = self.options.row_codec.encode(&SegmentStats::new(slots))?;
+        let rows = self.options.row_codec.encode(&slots)?;
+        let (stats, rows) = match &self.options.encryption_key {
+            Some(key) => (key.encrypt(&stats)?, key.encrypt(&rows)?),
+            None => (stats, rows),
+        };
+
+        let file = MessageFile {
+            filename: segment::FILENAME.to_owned(),
+            data: rows.into_bytes(),
+        };
+        Ok((segment::content(&stats), vec![file]))
+    }
+
+    /// slots of a packed or segment message with text `text`, `None` when it holds a single row
+    async fn decode_slots(
+        &self,
+        message: &Message,
+        text: &str,
+    ) -> eyre::Result<Option<Vec<Option<DataRow>>>> {
+        if let Some(slots) = self.decode_pack(message.id, text)? {
+            return Ok(Some(slots));
+        }
+        if segment::parse(text).is_none() {
+            return Ok(None);
+        }
+
+        let rows = String::from_utf8(self.attachment(message, segment::FILENAME).await?)?;
+        let (rows, _) = self.decrypt(message.id, rows)?;
+        utils::from_discord_json(&rows).map(Some)
+    }
+
+    /// statistics of a segment message with text `text`
+    fn decode_segment_stats(
+        &self,
+        message_id: MessageId,
+        text: &str,
+    ) -> eyre::Result<Option<SegmentStats>> {
+        let text = match segment::parse(text) {
+            Some(text) => text.to_owned(),
+            None => return Ok(None),
+        };
+
+        let (text, _) = self.decrypt(message_id, text)?;
+        utils::from_discord_json(&text).map(Some)
+    }
+
+    /// row held by `message` with text `text`, downloading its attachments when referenced
+    async fn decode_row(&self, message: &Message, text: String) -> eyre::Result<DataRow> {
+        let text = match attachment::parse_stub(&text) {
+            Some(filename) if !message.attachments.is_empty() => {
+                String::from_utf8(self.attachment(message, filename).await?)?
+            }
+            _ => text,
+        };
+
+        let (text, encrypted) = self.decrypt(message.id, text)?;
+        if message.attachments.is_empty() {
+            return Ok(self.text_row(message, text));
+        }
+
+        let mut json = match utils::from_discord_json::<Json>(&text) {
+            Ok(json) => json,
+            Err(_) => return Ok(self.text_row(message, text)),
+        };
+
+        let mut files = HashMap::new();
+        for filename in attachment::referenced_files(&json) {
+            let data = self.attachment(message, &filename).await?;
+            let data = match &self.options.encryption_key {
+                Some(key) if encrypted => {
+                    key.decrypt_bytes(&data).ok_or(StorageError::Decryption {
+                        message_id: message.id,
+                    })?
+                }
+                _ => data,
+            };
+
+            files.insert(filename, data);
+        }
+        attachment::restore_bytea(&mut json, &files)?;
+
+        Ok(serde_json::from_value(json).unwrap_or_else(|_| self.text_row(message, text)))
+    }
+
+    /// row of `text`, a message holding no stored row only has the `content` column
+    /// and the parsed chat columns when they are enabled
+    fn text_row(&self, message: &Message, text: String) -> DataRow {
+        if let Ok(row) = utils::from_discord_json(&text) {
+            return row;
+        }
+
+        let mut columns = HashMap::from([("content".to_owned(), Value::Str(text))]);
+        if self.options.chat_columns {
+            let reply_to = message
+                .message_reference
+                .as_ref()
+                .and_then(|reference| reference.message_id);
+            columns.extend(chat::columns(&message.content, reply_to));
+        }
+
+        DataRow::Map(columns)
+    }
+
+    /// `text` decrypted with the configured key, and whether it was encrypted at all
+    fn decrypt(&self, message_id: MessageId, text: String) -> eyre::Result<(String, bool)> {
+        if !encryption::is_encrypted(&text) {
+            return Ok((text, false));
+        }
+
+        let key = self
+            .options
+            .encryption_key
+            .as_ref()
+            .ok_or(StorageError::MissingEncryptionKey { message_id })?;
+        let text = key
+            .decrypt(&text)
+            .ok_or(StorageError::Decryption { message_id })?;
+
+        Ok((text, true))
+    }
+
+    async fn attachment(&self, message: &Message, filename: &str) -> eyre::Result<Vec<u8>> {
+        let attachment = message
+            .attachments
+            .iter()
+            .find(|attachment| attachment.filename == filename)
+            .ok_or_else(|| {
+                eyre::eyre!("missing attachment {filename} of message {}", message.id)
+            })?;
+
+        self.discord.download_attachment(attachment).await
+    }
+
+    /// sends `content` as one message, or as continuation messages followed by their head
+    async fn send_row(
+        &self,
+        channel_id: ChannelId,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        self.row_counts.remove(channel_id);
+        let chunk_ids = match chunk::split(&content)? {
+            Some(chunks) => self.send_chunks(channel_id, chunks).await?,
+            None => vec![],
+        };
+        let content = if chunk_ids.is_empty() {
+            content
+        } else {
+            chunk::head(&chunk_ids)
+        };
+
+        let message = if files.is_empty() {
+            self.discord.send_message(channel_id, content).await?
+        } else {
+            self.discord
+                .send_message_with_files(channel_id, content, files)
+                .await?
+        };
+        self.heads.insert(channel_id, message.id, chunk_ids);
+
+        Ok(message)
+    }
+
+    /// replaces the row held by `head`, continuation messages included
+    async fn edit_row(
+        &self,
+        channel_id: ChannelId,
+        head: &Message,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<()> {
+        // an edited packed or segment message may hold a different number of rows
+        self.row_counts.remove(channel_id);
+        let chunk_ids = match chunk::split(&content)? {
+            Some(chunks) => self.send_chunks(channel_id, chunks).await?,
+            None => vec![],
+        };
+        let content = if chunk_ids.is_empty() {
+            content
+        } else {
+            chunk::head(&chunk_ids)
+        };
+
+        // a plain edit keeps the attachments, so they are replaced whenever the row had any
+        if files.is_empty() && head.attachments.is_empty() {
+            self.discord
+                .edit_message(channel_id, head.id, content)
+                .await?;
+        } else {
+            self.discord
+                .edit_message_with_files(channel_id, head.id, content, files)
+                .await?;
+        }
+        self.heads.insert(channel_id, head.id, chunk_ids);
+
+        self.delete_chunks(channel_id, head).await
+    }
+
+    /// Replaces `channel` with an empty channel of the same name, position and settings,
+    /// holding its schema. No other pin is carried over: index messages are not rebuilt, so
+    /// indexed tables are never recreated, and a copy of a message pinned by a person would be
+    /// sent by the bot and read as a row. The old channel is deleted last, so a failure before
+    /// that leaves the table as it was next to an unused channel.
+    async fn recreate_channel(&self, channel: GuildChannel) -> eyre::Result<()> {
+        let schema = self.schema(channel.clone()).await?;
+
+        let mut builder = CreateChannel::default();
+        builder
+            .name(&channel.name)
+            .kind(channel.kind)
+            .nsfw(channel.nsfw);
+        if let Ok(position) = u32::try_from(channel.position) {
+            builder.position(position);
+        }
+        if let Some(topic) = &channel.topic {
+            builder.topic(topic);
+        }
+        if let Some(parent_id) = channel.parent_id {
+            builder.category(parent_id);
+        }
+        if let Some(rate_limit) = channel.rate_limit_per_user {
+            builder.rate_limit_per_user(rate_limit);
+        }
+        if !channel.permission_overwrites.is_empty() {
+            builder.permissions(channel.permission_overwrites.clone());
+        }
+
+        let fresh = self
+            .discord
+            .create_channel(self.storage_guild_id, builder)
+            .await?;
+
+        let content = self.schema_content(&schema)?;
+        let message = self.send_row(fresh.id, content, vec![]).await?;
+        self.discord.set_pin(fresh.id, message.id).await?;
+
+        self.discord.delete_channel(channel.id).await?;
+        self.schemas.remove(channel.id);
+        self.keys.remove(channel.id);
+        self.heads.remove(channel.id);
+        self.row_counts.remove(channel.id);
+        self.counters.remove(channel.id);
+        self.segment_buffer.take(channel.id);
+        self.indexes.remove_channel(channel.id);
+        self.schemas.insert(&fresh, schema);
+        self.directory.insert(fresh);
+
+        Ok(())
+    }
+
+    /// Whether the `removed` row messages and the `packs` slots are every row of `channel` as
+    /// counted by its last full scan, with enough of them to rather recreate the channel.
+    /// A row sent since, by this storage or anyone else, is the newest row message and not removed.
+    async fn deletes_every_row(
+        &self,
+        channel: &GuildChannel,
+        removed: &[MessageId],
+        packs: &[(MessageId, Vec<usize>)],
+    ) -> eyre::Result<bool> {
+        let rows = removed
+            .iter()
+            .map(|message_id| (*message_id, None))
+            .chain(packs.iter().flat_map(|(message_id, slots)| {
+                slots.iter().map(move |slot| (*message_id, Some(*slot)))
+            }))
+            .collect::<HashSet<_>>();
+        if rows.len() < TRUNCATE_MIN_ROWS || self.row_counts.get(channel.id) != Some(rows.len()) {
+            return Ok(false);
+        }
+        if !is_recreatable(&self.schema(channel.clone()).await?) {
+            return Ok(false);
+        }
+
+        let newest = match self.latest_row_message(channel.id).await? {
+            Some(message) => message.id,
+            None => return Ok(false),
+        };
+
+        Ok(rows.iter().any(|(message_id, _)| {
+            *message_id == newest
+                || self
+                    .heads
+                    .get(channel.id, *message_id)
+                    .unwrap_or_default()
+                    .contains(&newest)
+        }))
+    }
+
+    /// bulk deletes messages younger than 14 days, 100 per request, and the rest one by one
+    async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    ) -> eyre::Result<DeleteStats> {
+        let (batches, single) = bulk_delete::plan(message_ids, Utc::now().timestamp());
+        let concurrency = self.options.write_concurrency.max(1);
+
+        let bulk = stream::iter(batches)
+            .map(|batch| async move {
+                let len = batch.len();
+                self.discord
+                    .delete_messages(channel_id, batch)
+                    .await
+                    .map(|_| len)
+            })
+            .buffer_unordered(concurrency)
+            .try_fold(0, |bulk, len| future::ready(Ok(bulk + len)))
+            .await?;
+
+        let single = stream::iter(single)
+            .map(|message_id| self.discord.delete_message(channel_id, message_id))
+            .buffer_unordered(concurrency)
+            .try_fold(0, |single, _| future::ready(Ok(single + 1)))
+            .await?;
+
+        Ok(DeleteStats { bulk, single })
+    }
+
+    /// newest row message of the channel, pinned schema and index messages are skipped
+    async fn latest_row_message(&self, channel_id: ChannelId) -> eyre::Result<Option<Message>> {
+        let mut messages = self.discord.latest_message_stream(channel_id);
+        while let Some(message) = messages.try_next().await? {
+            if matches!(message.kind, MessageType::Regular) && !message.pinned {
+                return Ok(Some(message));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn pack_fits(&self, slots: &[Option<DataRow>]) -> eyre::Result<bool> {
+        Ok(self.encode_pack(slots)?.chars().count() <= chunk::MESSAGE_CONTENT_LIMIT)
+    }
+
+    /// sends `slots` as a new packed message, or edits `message` to hold them
+    async fn write_pack(
+        &self,
+        channel_id: ChannelId,
+        message: Option<&Message>,
+        slots: &[Option<DataRow>],
+    ) -> eyre::Result<()> {
+        let content = self.encode_pack(slots)?;
+
+        match message {
+            Some(message) => self.edit_row(channel_id, message, content, vec![]).await,
+            None => self.send_row(channel_id, content, vec![]).await.map(|_| ()),
+        }
+    }
+
+    /// edits the packed or segment message `message` with text `text` to hold `slots`
+    async fn write_slots(
+        &self,
+        channel_id: ChannelId,
+        message: &Message,
+        text: &str,
+        slots: &[Option<DataRow>],
+    ) -> eyre::Result<()> {
+        if segment::parse(text).is_none() {
+            return self.write_pack(channel_id, Some(message), slots).await;
+        }
+
+        let (content, files) = self.encode_segment(slots)?;
+        self.edit_row(channel_id, message, content, files).await
+    }
+
+    /// replaces the rows in `rows` slots of the packed or segment message `message_id`
+    async fn update_slots(
+        &self,
+        table_name: &str,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        rows: Vec<(usize, DataRow)>,
+    ) -> gluesql::Result<()> {
+        let row_not_found = |slot| StorageError::RowNotFound {
+            table_name: table_name.to_owned(),
+            key: packed::key(message_id, slot),
+        };
+
+        let message = match self.discord.get_message(channel_id, message_id).await {
+            Ok(message) => message,
+            Err(err) if is_unknown_message(&err) => return Err(row_not_found(rows[0].0).into()),
+            Err(err) => return Err(gluesql::Error::Storage(err.into())),
+        };
+        let text = self.message_text(&message).await.into_storage_err()?;
+        let mut slots = self
+            .decode_slots(&message, &text)
+            .await
+            .into_storage_err()?
+            .unwrap_or_default();
+
+        for (slot, row) in rows {
+            match slots.get_mut(slot) {
+                Some(stored @ Some(_)) => *stored = Some(row),
+                _ => return Err(row_not_found(slot).into()),
+            }
+        }
+
+        self.write_slots(channel_id, &message, &text, &slots)
+            .await
+            .into_storage_err()
+    }
+
+    /// sends `rows` in order as segment messages of up to `segment_rows` rows each
+    async fn append_segments(
+        &self,
+        channel_id: ChannelId,
+        rows: Vec<DataRow>,
+        segment_rows: usize,
+    ) -> eyre::Result<()> {
+        let mut rows = rows.into_iter().map(Some).peekable();
+        while rows.peek().is_some() {
+            let slots = rows.by_ref().take(segment_rows.max(1)).collect::<Vec<_>>();
+            let (content, files) = self.encode_segment(&slots)?;
+
+            self.send_row(channel_id, content, files).await?;
+        }
+
+        Ok(())
+    }
+
+    /// uploads the rows waiting in the segment buffer of `channel_id`
+    async fn flush_segments(&self, channel_id: ChannelId) -> eyre::Result<()> {
+        let rows = self.segment_buffer.take(channel_id);
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let segment_rows = rows.len();
+        self.append_segments(channel_id, rows, segment_rows).await
+    }
+
+    /// Fills the newest packed message of the channel, then new packed messages, in row order.
+    /// A row too long to share a message is sent on its own.
+    async fn append_packed(&self, channel_id: ChannelId, rows: Vec<DataRow>) -> eyre::Result<()> {
+        let mut pack = match self.latest_row_message(channel_id).await? {
+            Some(message) => self
+                .decode_pack(message.id, &self.content(&message))?
+                .map(|slots| (Some(message), slots)),
+            None => None,
+        };
+        let mut changed = false;
+
+        for row in rows {
+            if let Some((_, slots)) = pack.as_mut() {
+                slots.push(Some(row.clone()));
+                if self.pack_fits(slots)? {
+                    changed = true;
+                    continue;
+                }
+                slots.pop();
+            }
+
+            if let Some((message, slots)) = pack.take().filter(|_| changed) {
+                self.write_pack(channel_id, message.as_ref(), &slots)
+                    .await?;
+            }
+
+            let slots = vec![Some(row.clone())];
+            changed = self.pack_fits(&slots)?;
+            if changed {
+                pack = Some((None, slots));
+            } else {
+                let (content, files) = self.encode_row(&row)?;
+                self.send_row(channel_id, content, files).await?;
+            }
+        }
+
+        if let Some((message, slots)) = pack.filter(|_| changed) {
+            self.write_pack(channel_id, message.as_ref(), &slots)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// writes awaited at once in `channel_id`, held to the requests left in its rate limit bucket
+    async fn write_concurrency(&self, channel_id: ChannelId) -> usize {
+        let concurrency = self.options.write_concurrency.max(1);
+
+        match self.discord.send_capacity(channel_id).await {
+            Some(capacity) => concurrency.min(capacity).max(1),
+            None => concurrency,
+        }
+    }
+
+    async fn send_chunks(
+        &self,
+        channel_id: ChannelId,
+        chunks: Vec<String>,
+    ) -> eyre::Result<Vec<MessageId>> {
+        let mut chunk_ids = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let message = self.discord.send_message(channel_id, chunk).await?;
+            chunk_ids.push(message.id);
+        }
+
+        Ok(chunk_ids)
+    }
+
+    async fn delete_chunks(&self, channel_id: ChannelId, head: &Message) -> eyre::Result<()> {
+        for chunk_id in chunk::parse_head(&head.content).unwrap_or_default() {
+            self.discord.delete_message(channel_id, chunk_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// rows of a primary key table sorted by key with their metadata columns,
+    /// rebuilding its key map on the way
+    async fn primary_key_rows(
+        &self,
+        channel_id: ChannelId,
+        primary_key: usize,
+    ) -> gluesql::Result<Vec<(Key, DataRow)>> {
+        let messages = self.row_messages(channel_id).await.into_storage_err()?;
+
+        let mut keys = HashMap::with_capacity(messages.len());
+        let mut rows = Vec::with_capacity(messages.len());
+        for (message, _, row) in messages {
+            let key = primary_key_of(&row, primary_key)?;
+            let value = primary_value_of(&row, primary_key)?.clone();
+
+            keys.insert(key.clone(), (value, message.id));
+            rows.push((key, self.with_metadata(&message, row)));
+        }
+
+        self.keys.replace(channel_id, keys);
+        rows.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        Ok(rows)
+    }
+
+    /// message holding the row `key` and its slot if packed, `None` if no row has the primary key
+    async fn row_position(
+        &self,
+        channel: GuildChannel,
+        key: &Key,
+    ) -> gluesql::Result<Option<(MessageId, Option<usize>)>> {
+        let channel_id = channel.id;
+
+        match self.primary_key(channel).await? {
+            Some(primary_key) => Ok(self
+                .message_ids_by_keys(channel_id, primary_key, [key])
+                .await?
+                .remove(key)
+                .map(|message_id| (message_id, None))),
+            None => match key {
+                Key::Str(id) => packed::parse_key(id)
+                    .map(Some)
+                    .ok_or_else(|| gluesql::Error::Storage(format!("invalid key: {id}").into())),
+                _ => Err(gluesql::Error::Storage("invalid key".into())),
+            },
+        }
+    }
+
+    /// Messages holding the rows of `keys` that exist. The key map is read once, and read
+    /// again when it misses a key, since another writer may have added the row since.
+    async fn message_ids_by_keys<'a>(
+        &self,
+        channel_id: ChannelId,
+        primary_key: usize,
+        keys: impl IntoIterator<Item = &'a Key>,
+    ) -> gluesql::Result<HashMap<Key, MessageId>> {
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        if keys
+            .iter()
+            .any(|key| !matches!(self.keys.get(channel_id, key), Some(Some(_))))
+        {
+            self.load_keys(channel_id, primary_key).await?;
+        }
+
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| Some((key.clone(), self.keys.get(channel_id, key)??)))
+            .collect())
+    }
+
+    /// Reads the key map of a primary key table from its `<keys>` message. A table without
+    /// one, created before key maps were stored, is scanned once to build and store it.
+    async fn load_keys(&self, channel_id: ChannelId, primary_key: usize) -> gluesql::Result<()> {
+        let message = self.keys_message(channel_id).await.into_storage_err()?;
+        let message = match message {
+            Some(message) => message,
+            None => {
+                self.primary_key_rows(channel_id, primary_key).await?;
+
+                return self.save_keys(channel_id).await.into_storage_err();
+            }
+        };
+
+        let data = self.key_data(&message).await.into_storage_err()?;
+        self.keys.load(channel_id, message.id, data)
+    }
+
+    async fn key_data(&self, message: &Message) -> eyre::Result<KeyData> {
+        let text = String::from_utf8(self.attachment(message, key_map::KEYS_FILENAME).await?)?;
+        let (text, _) = self.decrypt(message.id, text)?;
+
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Writes the key map of `channel_id` to its `<keys>` message, pinning one for tables
+    /// without it. Rows are written before the map, so a map missing rows that were sent
+    /// just before a failure is repaired by the next scan of the table and the write after it.
+    async fn save_keys(&self, channel_id: ChannelId) -> eyre::Result<()> {
+        let (message_id, data) = match self.keys.data(channel_id) {
+            Some(keys) => keys,
+            None => return Ok(()),
+        };
+        let message_id = match message_id {
+            Some(message_id) => Some(message_id),
+            None => self
+                .keys_message(channel_id)
+                .await?
+                .map(|message| message.id),
+        };
+
+        let text = serde_json::to_string(&data)?;
+        let text = match &self.options.encryption_key {
+            Some(key) => key.encrypt(&text)?,
+            None => text,
+        };
+        let file = MessageFile {
+            filename: key_map::KEYS_FILENAME.to_owned(),
+            data: text.into_bytes(),
+        };
+
+        let message_id = match message_id {
+            Some(message_id) => {
+                self.discord
+                    .edit_message_with_files(
+                        channel_id,
+                        message_id,
+                        key_map::HEAD.to_owned(),
+                        vec![file],
+                    )
+                    .await?;
+
+                message_id
+            }
+            None => {
+                let message = self
+                    .discord
+                    .send_message_with_files(channel_id, key_map::HEAD.to_owned(), vec![file])
+                    .await?;
+                self.discord.set_pin(channel_id, message.id).await?;
+
+                message.id
+            }
+        };
+        self.keys.set_message(channel_id, message_id);
+
+        Ok(())
+    }
+
+    async fn keys_message(&self, channel_id: ChannelId) -> eyre::Result<Option<Message>> {
+        let pins = self.discord.get_pins(channel_id).await?;
+
+        Ok(pins
+            .into_iter()
+            .find(|message| is_row_message(message) && message.content == key_map::HEAD))
+    }
+
+    /// Reads the reaction counters of `channel_id` for the `_reactions` column, when
+    /// [`StorageOptions::reaction_columns`] is enabled.
+    async fn read_counters(&self, channel_id: ChannelId) -> gluesql::Result<()> {
+        if !self.options.reaction_columns {
+            return Ok(());
+        }
+
+        self.load_counters(channel_id).await.into_storage_err()
+    }
+
+    /// Reads the reaction counters of `channel_id` from its `<counters>` message, unless they
+    /// were read from its current attachment already. A channel without one counts nothing.
+    async fn load_counters(&self, channel_id: ChannelId) -> eyre::Result<()> {
+        let message = self.counters_message(channel_id).await?;
+        let current = message.as_ref().and_then(|message| {
+            let attachment = message.attachments.first()?;
+
+            Some((message.id, attachment.id))
+        });
+        if self.counters.is_current(channel_id, current) {
+            return Ok(());
+        }
+
+        let data = match &message {
+            Some(message) => {
+                let text =
+                    String::from_utf8(self.attachment(message, counter::COUNTERS_FILENAME).await?)?;
+                let (text, _) = self.decrypt(message.id, text)?;
+
+                serde_json::from_str(&text)?
+            }
+            None => CounterData::default(),
+        };
+        self.counters.load(channel_id, current, data);
+
+        Ok(())
+    }
+
+    /// Writes the reaction counters of `channel_id` to its `<counters>` message, pinning one
+    /// the first time the channel counts.
+    async fn save_counters(&self, channel_id: ChannelId) -> eyre::Result<()> {
+        let (message_id, data) = match self.counters.data(channel_id) {
+            Some(counters) => counters,
+            None => return Ok(()),
+        };
+
+        let text = serde_json::to_string(&data)?;
+        let text = match &self.options.encryption_key {
+            Some(key) => key.encrypt(&text)?,
+            None => text,
+        };
+        let file = MessageFile {
+            filename: counter::COUNTERS_FILENAME.to_owned(),
+            data: text.into_bytes(),
+        };
+
+        let message = match message_id {
+            Some(message_id) => {
+                self.discord
+                    .edit_message_with_files(
+                        channel_id,
+                        message_id,
+                        counter::HEAD.to_owned(),
+                        vec![file],
+                    )
+                    .await?
+            }
+            None => {
+                let message = self
+                    .discord
+                    .send_message_with_files(channel_id, counter::HEAD.to_owned(), vec![file])
+                    .await?;
+                self.discord.set_pin(channel_id, message.id).await?;
+
+                message
+            }
+        };
+        if let Some(attachment) = message.attachments.first() {
+            self.counters
+                .set_message(channel_id, (message.id, attachment.id));
+        }
+
+        Ok(())
+    }
+
+    async fn counters_message(&self, channel_id: ChannelId) -> eyre::Result<Option<Message>> {
+        let pins = self.discord.get_pins(channel_id).await?;
+
+        Ok(pins
+            .into_iter()
+            .find(|message| is_row_message(message) && message.content == counter::HEAD))
+    }
+
+    /// the pinned schema message, the oldest pin of the bot that is not an index, key map or
+    /// counters message, people may pin other messages of the channel
+    async fn schema_message(&self, channel_id: ChannelId) -> eyre::Result<Option<Message>> {
+        let pins = self.discord.get_pins(channel_id).await?;
+
+        Ok(pins.into_iter().rev().find(|message| {
+            is_row_message(message)
+                && index::parse_head(&message.content).is_none()
+                && message.content != key_map::HEAD
+                && message.content != counter::HEAD
+        }))
+    }
+
+    fn schema_content(&self, schema: &Schema) -> eyre::Result<String> {
+        let content = utils::to_discord_json(schema)?;
+
+        match &self.options.encryption_key {
+            Some(key) if self.options.encrypt_schemas => key.encrypt(&content),
+            _ => Ok(content),
+        }
+    }
+
+    /// rewrites the schema message of `channel`, pinning a new one for tables without it
+    async fn write_schema(&self, channel: &GuildChannel, schema: Schema) -> eyre::Result<()> {
+        let content = self.schema_content(&schema)?;
+
+        match self.schema_message(channel.id).await? {
+            Some(message) => self.edit_row(channel.id, &message, content, vec![]).await?,
+            None => {
+                let message = self.send_row(channel.id, content, vec![]).await?;
+                self.discord.set_pin(channel.id, message.id).await?;
+            }
+        }
+        self.schemas.insert(channel, schema);
+
+        Ok(())
+    }
+
+    async fn index_message(
+        &self,
+        channel_id: ChannelId,
+        index_name: &str,
+    ) -> eyre::Result<Message> {
+        self.discord
+            .get_pins(channel_id)
+            .await?
+            .into_iter()
+            .find(|message| index::parse_head(&message.content) == Some(index_name))
+            .ok_or_else(|| eyre::eyre!("index message not found: {index_name}"))
+    }
+
+    /// index data with the id of the message holding it, read from the pinned index message once
+    async fn load_index(
+        &self,
+        channel_id: ChannelId,
+        index_name: &str,
+    ) -> eyre::Result<(MessageId, IndexData)> {
+        if let Some(index) = self.indexes.get(channel_id, index_name) {
+            return Ok(index);
+        }
+
+        let message = self.index_message(channel_id, index_name).await?;
+        let text = String::from_utf8(self.attachment(&message, index::INDEX_FILENAME).await?)?;
+        let (text, _) = self.decrypt(message.id, text)?;
+        let data: IndexData = serde_json::from_str(&text)?;
+
+        self.indexes
+            .insert(channel_id, index_name, message.id, data.clone());
+        Ok((message.id, data))
+    }
+
+    fn index_file(&self, data: &IndexData) -> eyre::Result<MessageFile> {
+        let text = serde_json::to_string(data)?;
+        let text = match &self.options.encryption_key {
+            Some(key) => key.encrypt(&text)?,
+            None => text,
+        };
+
+        Ok(MessageFile {
+            filename: index::INDEX_FILENAME.to_owned(),
+            data: text.into_bytes(),
+        })
+    }
+
+    /// updates every index of `channel` after rows were removed from or written to messages
+    async fn sync_indexes(
+        &self,
+        channel: GuildChannel,
+        removed: &[MessageId],
+        written: &[(MessageId, DataRow)],
+    ) -> eyre::Result<()> {
+        if removed.is_empty() && written.is_empty() {
+            return Ok(());
+        }
+
+        let channel_id = channel.id;
+        let schema = self.schema(channel).await?;
+        for schema_index in &schema.indexes {
+            let column = match index::column_of(&schema_index.expr) {
+                Some(column) => column,
+                None => continue,
+            };
+
+            let (message_id, mut data) = self.load_index(channel_id, &schema_index.name).await?;
+            data.remove(removed);
+            for (row_id, row) in written {
+                let value = index::value_of(row, schema.column_defs.as_deref(), column);
+                data.insert(value, *row_id);
+            }
+
+            let file = self.index_file(&data)?;
+            self.discord
+                .edit_message_with_files(
+                    channel_id,
+                    message_id,
+                    index::head(&schema_index.name),
+                    vec![file],
+                )
+                .await?;
+            self.indexes
+                .insert(channel_id, &schema_index.name, message_id, data);
+        }
+
+        Ok(())
+    }
+
+    /// channel and schema of a table being altered
+    async fn altered_table(&self, table_name: &str) -> gluesql::Result<(GuildChannel, Schema)> {
+        let channel = self
+            .channel(&table_name.to_lowercase())
+            .await
+            .into_storage_err()?
+            .ok_or_else(|| AlterTableError::TableNotFound(table_name.to_owned()))?;
+        let schema = self.schema(channel.clone()).await.into_storage_err()?;
+
+        Ok((channel, schema))
+    }
+
+    /// re-encodes every row of the channel after `update` changed its values
+    async fn rewrite_rows(
+        &self,
+        channel_id: ChannelId,
+        update: impl Fn(&mut Vec<Value>),
+    ) -> eyre::Result<()> {
+        let mut packs: Vec<Message> = Vec::new();
+        for (message, slot, row) in self.row_messages(channel_id).await? {
+            if slot.is_some() {
+                if !packs.iter().any(|pack| pack.id == message.id) {
+                    packs.push(message);
+                }
+
+                continue;
+            }
+
+            let mut values = match row {
+                DataRow::Vec(values) => values,
+                DataRow::Map(_) => continue,
+            };
+            update(&mut values);
+
+            let (content, files) = self.encode_row(&DataRow::Vec(values))?;
+            self.edit_row(channel_id, &message, content, files).await?;
+        }
+
+        for message in packs {
+            let text = self.message_text(&message).await?;
+            let mut slots = self
+                .decode_slots(&message, &text)
+                .await?
+                .unwrap_or_default();
+            for row in slots.iter_mut().flatten() {
+                if let DataRow::Vec(values) = row {
+                    update(values);
+                }
+            }
+
+            self.write_slots(channel_id, &message, &text, &slots)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// writes the open transaction buffered for `table_name`
+    fn buffered(&self, table_name: &str) -> Option<&TableWrites> {
+        self.transaction.as_ref()?.table(table_name)
+    }
+
+    /// sends the buffered writes of one table, deletes first, then updates, then appended rows
+    async fn apply_writes(&mut self, table_name: &str, writes: TableWrites) -> gluesql::Result<()> {
+        let mut keys = Vec::new();
+        let mut rows = Vec::new();
+        for (key, row) in writes.changes {
+            match row {
+                Some(row) => rows.push((key, row)),
+                None => keys.push(key),
+            }
+        }
+
+        self.delete_data(table_name, keys).await?;
+        self.insert_data(table_name, rows).await?;
+        self.append_data(
+            table_name,
+            writes.appended.into_iter().map(|(_, row)| row).collect(),
+        )
+        .await
+    }
+
+    pub async fn get_schema(&self, channel: GuildChannel) -> eyre::Result<Schema> {
+        let message = self.schema_message(channel.id).await?;
+        let message = match message {
+            Some(msg) => msg,
+            None => {
+                return Ok(Schema {
+                    table_name: channel.name,
+                    column_defs: None,
+                    indexes: vec![],
+                    engine: None,
+                    created: Utc::now().naive_utc(),
+                })
+            }
+        };
+
+        let content = self.message_text(&message).await?;
+        let (content, _) = self.decrypt(message.id, content)?;
+
+        let schema: Schema = utils::from_discord_json(&content)?;
+        Ok(schema)
+    }
+}
+
+/// whether `message` was sent by a bot and so may hold rows, messages of people are chat
+fn is_row_message(message: &Message) -> bool {
+    message.author.bot
+}
+
+/// whether the channel of a table can be replaced by an empty one holding `schema`,
+/// index messages are not rebuilt and a schemaless channel may be a chat channel
+fn is_recreatable(schema: &Schema) -> bool {
+    schema.column_defs.is_some() && schema.indexes.is_empty()
+}
+
+/// `column {operator} value` of a filtered scan, the column by its position in the row
+#[derive(Clone, Copy)]
+struct ColumnFilter<'a> {
+    column: usize,
+    operator: &'a IndexOperator,
+    value: &'a Value,
+}
+
+impl ColumnFilter<'_> {
+    fn matches(&self, row: &DataRow) -> bool {
+        match row {
+            DataRow::Vec(values) => matches!(
+                values.get(self.column),
+                Some(value) if index::matches(value, self.operator, self.value)
+            ),
+            DataRow::Map(_) => false,
+        }
+    }
+
+    /// whether no row of a segment with `stats` can match
+    fn excludes(&self, stats: &SegmentStats) -> bool {
+        stats.excludes(self.column, self.operator, self.value)
+    }
+}
+
+/// key of a row of a table without a primary key, packed rows are keyed by their slot too
+fn row_key(message_id: MessageId, slot: Option<usize>) -> Key {
+    match slot {
+        Some(slot) => packed::key(message_id, slot),
+        None => Key::Str(message_id.0.to_string()),
+    }
+}
+
+fn primary_key_of(row: &DataRow, primary_key: usize) -> gluesql::Result<Key> {
+    primary_value_of(row, primary_key)?.try_into()
+}
+
+fn primary_value_of(row: &DataRow, primary_key: usize) -> gluesql::Result<&Value> {
+    match row {
+        DataRow::Vec(values) => values
+            .get(primary_key)
+            .ok_or_else(|| gluesql::Error::Storage("primary key value not found in row".into())),
+        DataRow::Map(_) => Err(gluesql::Error::Storage(
+            "schemaless row in primary key table".into(),
+        )),
+    }
+}
+
+trait IntoStorageErr<T> {
+    fn into_storage_err(self) -> gluesql::Result<T>;
+}
+
+/// [`StorageError`]s are unwrapped from the report so they stay downcastable
+impl<T> IntoStorageErr<T> for eyre::Result<T> {
+    fn into_storage_err(self) -> gluesql::Result<T> {
+        self.map_err(|report| match report.downcast::<StorageError>() {
+            Ok(err) => err.into(),
+            Err(report) => gluesql::Error::Storage(report.into()),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> Store for DiscordStorage<T> {
+    async fn fetch_schema(&self, channel_name: &str) -> gluesql::Result<Option<Schema>> {
+        debug::time!("fetch_schema", {
+            let channel_name = channel_name.to_lowercase();
+
+            let channel = self.channel(&channel_name).await.into_storage_err()?;
+
+            match channel {
+                Some(channel) => {
+                    let schema = self.schema(channel).await.into_storage_err()?;
+
+                    Ok(Some(self.with_metadata_columns(schema)))
+                }
+                None => Ok(None),
+            }
+        })
+    }
+
+    async fn fetch_all_schemas(&self) -> gluesql::Result<Vec<Schema>> {
+        debug::time!("fetch_all_schemas", {
+            let channels = self.channels().await.into_storage_err()?;
+
+            let mut schemas = Vec::new();
+            for channel in channels {
+                let schema = self.schema(channel).await.into_storage_err()?;
+                schemas.push(self.with_metadata_columns(schema));
+            }
+
+            Ok(schemas)
+        })
+    }
+
+    async fn fetch_data(&self, channel_name: &str, key: &Key) -> gluesql::Result<Option<DataRow>> {
+        debug::time!("fetch_data", {
+            let channel_name = channel_name.to_lowercase();
+
+            let channel = self
+                .channel(&channel_name)
+                .await
+                .into_storage_err()?
+                .ok_or_else(|| gluesql::Error::Storage("fetch_data) not found channel".into()))?;
+            let channel_id = channel.id;
+
+            if let Some(row) = self
+                .buffered(&channel_name)
+                .and_then(|writes| writes.get(key))
+            {
+                return Ok(row.cloned());
+            }
+
+            let (message_id, slot) = match self.row_position(channel, key).await? {
+                Some(position) => position,
+                None => return Ok(None),
+            };
+
+            let message = match self.discord.get_message(channel_id, message_id).await {
+                Ok(message) => message,
+                Err(err) if is_unknown_message(&err) => return Ok(None),
+                Err(err) => return Err(gluesql::Error::Storage(err.into())),
+            };
+            self.read_counters(channel_id).await?;
+
+            let content = self.content(&message);
+            let rows = self
+                .decode_message(message, content)
+                .await
+                .into_storage_err()?;
+
+            Ok(rows
+                .into_iter()
+                .find(|(_, row_slot, _)| *row_slot == slot)
+                .map(|(message, _, row)| self.with_metadata(&message, row)))
+        })
+    }
+
+    /// Rows of [`DiscordStorage::scan_stream`]. On a multi-thread runtime the rows of a table
+    /// without a primary key or buffered writes are read one history page at a time as gluesql
+    /// advances the iterator, `RowIter` being synchronous at this gluesql version the iterator
+    /// blocks the thread on each page request. A current thread runtime can't be blocked, there
+    /// and for other tables the rows are collected whole before returning.
+    async fn scan_data(&self, channel_name: &str) -> gluesql::Result<RowIter> {
+        debug::time!("scan_data", {
+            if let Ok(handle) = Handle::try_current() {
+                if handle.runtime_flavor() == RuntimeFlavor::MultiThread {
+                    if let Some(channel_id) = self.paged_channel(channel_name).await? {
+                        let rows = PagedRows::new(self.reader(), handle, channel_id);
+
+                        return Ok(Box::new(rows) as RowIter);
+                    }
+                }
+            }
+
+            let rows = self
+                .scan_stream(channel_name)
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            Ok(Box::new(rows.into_iter().map(Ok)) as RowIter)
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> StoreMut for DiscordStorage<T> {
+    async fn insert_schema(&mut self, schema: &Schema) -> gluesql::Result<()> {
+        debug::time!("insert_schema", {
+            let channel_name = &schema.table_name.to_lowercase();
+
+            let channel = self.channel(channel_name).await.into_storage_err()?;
+
+            let channel = match channel {
+                Some(channel) => channel,
+                None => {
+                    let mut builder = CreateChannel::default();
+                    builder.name(&schema.table_name);
+
+                    let channel = self
+                        .discord
+                        .create_channel(self.storage_guild_id, builder)
+                        .await
+                        .into_storage_err()?;
+                    self.directory.insert(channel.clone());
+
+                    channel
+                }
+            };
+            let channel_id = channel.id;
+
+            let pin_messages = self.discord.get_pins(channel_id).await.into_storage_err()?;
+
+            if !pin_messages.is_empty() {
+                return Err(gluesql::Error::Storage(
+                    format!("channel is already pinned: {channel_name}").into(),
+                ));
+            }
+
+            let content = self.schema_content(schema).into_storage_err()?;
+
+            let message = self
+                .send_row(channel_id, content, vec![])
+                .await
+                .into_storage_err()?;
+
+            self.discord
+                .set_pin(channel_id, message.id)
+                .await
+                .into_storage_err()?;
+            self.schemas.insert(&channel, schema.clone());
+
+            Ok(())
+        })
+    }
+
+    async fn delete_schema(&mut self, channel_name: &str) -> gluesql::Result<()> {
+        debug::time!("delete_schema", {
+            let channel_name = &channel_name.to_lowercase();
+
+            let channel_id = self.channel_id(channel_name).await.into_storage_err()?;
+            let channel_id = channel_id.ok_or_else(|| {
+                gluesql::Error::Storage("delete_schema) not found channel".into())
+            })?;
+
+            self.discord
+                .delete_channel(channel_id)
+                .await
+                .into_storage_err()?;
+            self.directory.remove(channel_name);
+            self.schemas.remove(channel_id);
+            self.keys.remove(channel_id);
+            self.heads.remove(channel_id);
+            self.row_counts.remove(channel_id);
+            self.counters.remove(channel_id);
+            self.segment_buffer.take(channel_id);
+            self.indexes.remove_channel(channel_id);
+            if let Some(write_set) = self.transaction.as_mut() {
+                write_set.remove(channel_name);
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn append_data(
+        &mut self,
+        channel_name: &str,
+        mut rows: Vec<DataRow>,
+    ) -> gluesql::Result<()> {
+        debug::time!("append_data", {
+            let storage = self;
+            let channel_name = &channel_name.to_lowercase();
+
+            let channel = storage.channel(channel_name).await.into_storage_err()?;
+            let channel = channel
+                .ok_or_else(|| gluesql::Error::Storage("append_data) not found channel".into()))?;
+            let channel_id = channel.id;
+            let primary_key = storage.primary_key(channel.clone()).await?;
+
+            if let Some(write_set) = storage.transaction.as_mut() {
+                for row in rows {
+                    match primary_key {
+                        Some(primary_key) => {
+                            let key = primary_key_of(&row, primary_key)?;
+                            write_set.table_mut(channel_name).upsert(key, row);
+                        }
+                        None => write_set.append(channel_name, row),
+                    }
+                }
+
+                return Ok(());
+            }
+
+            if storage.virtual_columns().any() {
+                let columns = storage.declared_columns(channel.clone()).await?;
+                for row in &mut rows {
+                    metadata::strip(row, columns);
+                }
+            }
+
+            let slotted = primary_key.is_none()
+                && (storage.options.segment_rows.is_some() || storage.options.pack_rows);
+            if slotted
+                && storage
+                    .schema(channel.clone())
+                    .await
+                    .into_storage_err()?
+                    .indexes
+                    .is_empty()
+            {
+                let appended = match storage.options.segment_rows {
+                    Some(segment_rows) => {
+                        let rows = storage.segment_buffer.push(channel_id, rows, segment_rows);
+
+                        storage
+                            .append_segments(channel_id, rows, segment_rows)
+                            .await
+                    }
+                    None => storage.append_packed(channel_id, rows).await,
+                };
+
+                return appended.into_storage_err();
+            }
+
+            if let Some(primary_key) = primary_key {
+                if !storage.keys.is_loaded(channel_id) {
+                    storage.load_keys(channel_id, primary_key).await?;
+                }
+            }
+
+            // rows of tables without a primary key are scanned in message order, so they are
+            // sent one at a time, messages sent at once get ids in the order they reach Discord
+            let concurrency = match primary_key {
+                Some(_) => storage.write_concurrency(channel_id).await,
+                None => 1,
+            };
+            let storage = &*storage;
+            let written = stream::iter(rows)
+                .map(|row| async move {
+                    let (content, files) = storage.encode_row(&row).into_storage_err()?;
+
+                    let message = storage
+                        .send_row(channel_id, content, files)
+                        .await
+                        .into_storage_err()?;
+
+                    if let Some(primary_key) = primary_key {
+                        let key = primary_key_of(&row, primary_key)?;
+                        let value = primary_value_of(&row, primary_key)?.clone();
+                        storage.keys.insert(channel_id, key, value, message.id);
+                    }
+
+                    Ok::<_, gluesql::Error>((message.id, row))
+                })
+                .buffered(concurrency)
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            if primary_key.is_some() {
+                storage.save_keys(channel_id).await.into_storage_err()?;
+            }
+
+            storage
+                .sync_indexes(channel, &[], &written)
+                .await
+                .into_storage_err()
+        })
+    }
+
+    async fn insert_data(
+        &mut self,
+        channel_name: &str,
+        mut rows: Vec<(Key, DataRow)>,
+    ) -> gluesql::Result<()> {
+        debug::time!("insert_data", {
+            let channel_name = &channel_name.to_lowercase();
+
+            let channel = self.channel(channel_name).await.into_storage_err()?;
+            let channel = channel
+                .ok_or_else(|| gluesql::Error::Storage("insert_data) not found channel".into()))?;
+            let channel_id = channel.id;
+            let primary_key = self.primary_key(channel.clone()).await?;
+
+            if let Some(write_set) = self.transaction.as_mut() {
+                let writes = write_set.table_mut(channel_name);
+                for (key, row) in rows {
+                    writes.upsert(key, row);
+                }
+
+                return Ok(());
+            }
+
+            if self.virtual_columns().any() {
+                let columns = self.declared_columns(channel.clone()).await?;
+                for (_, row) in &mut rows {
+                    metadata::strip(row, columns);
+                }
+            }
+
+            // a key map missing any of the keys is read again once before rows are sent for them
+            let found = match primary_key {
+                Some(primary_key) => {
+                    self.message_ids_by_keys(
+                        channel_id,
+                        primary_key,
+                        rows.iter().map(|(key, _)| key),
+                    )
+                    .await?
+                }
+                None => HashMap::new(),
+            };
+            let mut targets: Vec<(Key, Option<MessageId>, DataRow)> =
+                Vec::with_capacity(rows.len());
+            let mut packs: Vec<(MessageId, Vec<(usize, DataRow)>)> = Vec::new();
+            for (key, row) in rows {
+                if let Some(target) = targets.iter_mut().find(|(target, ..)| target == &key) {
+                    target.2 = row;
+                    continue;
+                }
+
+                let message_id = match primary_key {
+                    Some(_) => found.get(&key).copied(),
+                    None => {
+                        let message_id = match &key {
+                            Key::Str(message_id) => message_id,
+                            _ => {
+                                return Err(gluesql::Error::Storage(
+                                    eyre::eyre!("invalid key {key:?}").into(),
+                                ))
+                            }
+                        };
+
+                        let (message_id, slot) =
+                            packed::parse_key(message_id).ok_or_else(|| {
+                                gluesql::Error::Storage("insert_data) failed key parsing".into())
+                            })?;
+                        if let Some(slot) = slot {
+                            match packs.iter_mut().find(|(pack, _)| *pack == message_id) {
+                                Some((_, rows)) => rows.push((slot, row)),
+                                None => packs.push((message_id, vec![(slot, row)])),
+                            }
+
+                            continue;
+                        }
+
+                        Some(message_id)
+                    }
+                };
+                targets.push((key, message_id, row));
+            }
+
+            // slots of one packed message are written together, updates of the same message would race
+            for (message_id, rows) in packs {
+                self.update_slots(channel_name, channel_id, message_id, rows)
+                    .await?;
+            }
+
+            let concurrency = self.write_concurrency(channel_id).await;
+            let storage = &*self;
+            let writes = stream::iter(targets)
+                .map(|(key, message_id, row)| async move {
+                    let (content, files) = storage.encode_row(&row).into_storage_err()?;
+
+                    let message_id = match message_id {
+                        Some(message_id) => message_id,
+                        None => {
+                            let message = storage
+                                .send_row(channel_id, content, files)
+                                .await
+                                .into_storage_err()?;
+                            if let Some(primary_key) = primary_key {
+                                let value = primary_value_of(&row, primary_key)?.clone();
+                                storage.keys.insert(channel_id, key, value, message.id);
+                            }
+
+                            return Ok((None, message.id, row));
+                        }
+                    };
+
+                    // only a message Discord reports as unknown is missing, other failures are passed on
+                    let message = match storage.discord.get_message(channel_id, message_id).await {
+                        Ok(message) => message,
+                        Err(err) if primary_key.is_none() && is_unknown_message(&err) => {
+                            return Err(StorageError::RowNotFound {
+                                table_name: channel_name.to_owned(),
+                                key,
+                            }
+                            .into())
+                        }
+                        Err(err) => return Err(gluesql::Error::Storage(err.into())),
+                    };
+
+                    storage
+                        .edit_row(channel_id, &message, content, files)
+                        .await
+                        .into_storage_err()?;
+
+                    Ok::<_, gluesql::Error>((Some(message_id), message_id, row))
+                })
+                .buffered(concurrency)
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            if primary_key.is_some() && writes.iter().any(|(edited, ..)| edited.is_none()) {
+                self.save_keys(channel_id).await.into_storage_err()?;
+            }
+
+            let mut removed = Vec::new();
+            let mut written = Vec::with_capacity(writes.len());
+            for (edited, message_id, row) in writes {
+                removed.extend(edited);
+                written.push((message_id, row));
+            }
+
+            self.sync_indexes(channel, &removed, &written)
+                .await
+                .into_storage_err()
+        })
+    }
+
+    async fn delete_data(&mut self, channel_name: &str, keys: Vec<Key>) -> gluesql::Result<()> {
+        debug::time!("delete_data", {
+            let channel_name = &channel_name.to_lowercase();
+
+            let channel = self.channel(channel_name).await.into_storage_err()?;
+            let channel = channel
+                .ok_or_else(|| gluesql::Error::Storage("delete_data) not found channel".into()))?;
+            let channel_id = channel.id;
+            let primary_key = self.primary_key(channel.clone()).await?;
+
+            if let Some(write_set) = self.transaction.as_mut() {
+                let writes = write_set.table_mut(channel_name);
+                for key in keys {
+                    writes.delete(key);
+                }
+
+                return Ok(());
+            }
+
+            let found = match primary_key {
+                Some(primary_key) => {
+                    self.message_ids_by_keys(channel_id, primary_key, &keys)
+                        .await?
+                }
+                None => HashMap::new(),
+            };
+            let mut removed = Vec::with_capacity(keys.len());
+            let mut removed_keys = Vec::new();
+            let mut packs: Vec<(MessageId, Vec<usize>)> = Vec::new();
+            for key in keys {
+                let message_id = match primary_key {
+                    Some(_) => match found.get(&key) {
+                        Some(message_id) => {
+                            removed_keys.push(key);
+                            *message_id
+                        }
+                        None => continue,
+                    },
+                    None => {
+                        let key = match key {
+                            Key::Str(key) => key,
+                            _ => {
+                                return Err(gluesql::Error::Storage(
+                                    eyre::eyre!("invalid key {key:?}").into(),
+                                ))
+                            }
+                        };
+
+                        let (message_id, slot) = packed::parse_key(&key).ok_or_else(|| {
+                            gluesql::Error::Storage("delete_data) failed key parsing".into())
+                        })?;
+                        if let Some(slot) = slot {
+                            match packs.iter_mut().find(|(pack, _)| *pack == message_id) {
+                                Some((_, slots)) => slots.push(slot),
+                                None => packs.push((message_id, vec![slot])),
+                            }
+
+                            continue;
+                        }
+
+                        message_id
+                    }
+                };
+
+                removed.push(message_id);
+            }
+
+            if self
+                .deletes_every_row(&channel, &removed, &packs)
+                .await
+                .into_storage_err()?
+            {
+                tracing::debug!("delete_data) every row deleted, recreating {channel_name}");
+                return self.recreate_channel(channel).await.into_storage_err();
+            }
+
+            // continuation messages are known from the last full scan, which is how gluesql
+            // finds the rows to delete, other heads are fetched
+            let mut message_ids = Vec::with_capacity(removed.len());
+            for message_id in &removed {
+                let chunk_ids = match self.heads.get(channel_id, *message_id) {
+                    Some(chunk_ids) => chunk_ids,
+                    None => {
+                        let message = self
+                            .discord
+                            .get_message(channel_id, *message_id)
+                            .await
+                            .into_storage_err()?;
+
+                        chunk::parse_head(&message.content).unwrap_or_default()
+                    }
+                };
+
+                message_ids.push(*message_id);
+                message_ids.extend(chunk_ids);
+            }
+
+            for (message_id, deleted) in packs {
+                let message = self
+                    .discord
+                    .get_message(channel_id, message_id)
+                    .await
+                    .into_storage_err()?;
+                let text = self.message_text(&message).await.into_storage_err()?;
+                let mut slots = self
+                    .decode_slots(&message, &text)
+                    .await
+                    .into_storage_err()?
+                    .unwrap_or_default();
+                for slot in deleted {
+                    if let Some(row) = slots.get_mut(slot) {
+                        *row = None;
+                    }
+                }
+
+                if slots.iter().all(Option::is_none) {
+                    message_ids.push(message_id);
+                    message_ids.extend(chunk::parse_head(&message.content).unwrap_or_default());
+                } else {
+                    self.write_slots(channel_id, &message, &text, &slots)
+                        .await
+                        .into_storage_err()?;
+                }
+            }
+
+            for message_id in &message_ids {
+                self.heads.remove_head(channel_id, *message_id);
+            }
+            let stats = self
+                .delete_messages(channel_id, message_ids)
+                .await
+                .into_storage_err()?;
+            tracing::debug!(
+                "delete_data) {} bulk deleted, {} deleted one by one",
+                stats.bulk,
+                stats.single
+            );
+            self.delete_stats += stats;
+            self.row_counts.remove(channel_id);
+            for key in &removed_keys {
+                self.keys.remove_key(channel_id, key);
+            }
+            if !removed_keys.is_empty() {
+                self.save_keys(channel_id).await.into_storage_err()?;
+            }
+            if self.counters.is_loaded(channel_id) {
+                self.load_counters(channel_id).await.into_storage_err()?;
+                if self.counters.remove_rows(channel_id, &removed) {
+                    self.save_counters(channel_id).await.into_storage_err()?;
+                }
+            }
+
+            self.sync_indexes(channel, &removed, &[])
+                .await
+                .into_storage_err()
+        })
+    }
+}
+
+/// Indexes cover plain columns only, e.g. `CREATE INDEX idx_name ON User (name)`.
+#[async_trait(?Send)]
+impl<T: Transport> Index for DiscordStorage<T> {
+    async fn scan_indexed_data(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        asc: Option<bool>,
+        cmp_value: Option<(&IndexOperator, Value)>,
+    ) -> gluesql::Result<RowIter> {
+        debug::time!("scan_indexed_data", {
+            let channel_name = table_name.to_lowercase();
+            let channel = self
+                .channel(&channel_name)
+                .await
+                .into_storage_err()?
+                .ok_or_else(|| {
+                    gluesql::Error::Storage("scan_indexed_data) not found channel".into())
+                })?;
+            let channel_id = channel.id;
+            self.read_counters(channel_id).await?;
+
+            let schema = self.schema(channel.clone()).await.into_storage_err()?;
+            let column = schema
+                .indexes
+                .iter()
+                .find(|schema_index| schema_index.name == index_name)
+                .and_then(|schema_index| index::column_of(&schema_index.expr))
+                .ok_or_else(|| {
+                    gluesql::Error::Storage(
+                        format!("scan_indexed_data) not found index: {index_name}").into(),
+                    )
+                })?;
+            let cmp_value = cmp_value
+                .as_ref()
+                .map(|(operator, value)| (*operator, value));
+
+            // buffered writes are not in the stored index yet, so the table is filtered in memory
+            if self.buffered(&channel_name).is_some() {
+                let mut rows = Vec::new();
+                for row in self.scan_data(table_name).await? {
+                    let (key, row) = row?;
+                    let value = index::value_of(&row, schema.column_defs.as_deref(), column);
+
+                    let matched = match cmp_value {
+                        Some((operator, target)) => index::matches(&value, operator, target),
+                        None => true,
+                    };
+                    if matched {
+                        rows.push((value, (key, row)));
+                    }
+                }
+                index::sort(&mut rows, asc);
+
+                return Ok(Box::new(rows.into_iter().map(|(_, row)| Ok(row))) as RowIter);
+            }
+
+            let primary_key = self.primary_key(channel).await?;
+            let (_, data) = self
+                .load_index(channel_id, index_name)
+                .await
+                .into_storage_err()?;
+
+            let mut rows = Vec::new();
+            for message_id in data.scan(asc, cmp_value) {
+                let message = match self.discord.get_message(channel_id, message_id).await {
+                    Ok(message) => message,
+                    Err(err) if is_unknown_message(&err) => continue,
+                    Err(err) => return Err(gluesql::Error::Storage(err.into())),
+                };
+                let text = self.message_text(&message).await.into_storage_err()?;
+                let row = self.decode_row(&message, text).await.into_storage_err()?;
+
+                let key = match primary_key {
+                    Some(primary_key) => primary_key_of(&row, primary_key)?,
+                    None => Key::Str(message_id.0.to_string()),
+                };
+                rows.push((key, self.with_metadata(&message, row)));
+            }
+
+            Ok(Box::new(rows.into_iter().map(Ok)) as RowIter)
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> IndexMut for DiscordStorage<T> {
+    async fn create_index(
+        &mut self,
+        table_name: &str,
+        index_name: &str,
+        column: &OrderByExpr,
+    ) -> gluesql::Result<()> {
+        debug::time!("create_index", {
+            let channel_name = table_name.to_lowercase();
+            let channel = self
+                .channel(&channel_name)
+                .await
+                .into_storage_err()?
+                .ok_or_else(|| gluesql::Error::Storage("create_index) not found channel".into()))?;
+            let channel_id = channel.id;
+
+            let mut schema = self.schema(channel.clone()).await.into_storage_err()?;
+            if schema
+                .indexes
+                .iter()
+                .any(|schema_index| schema_index.name == index_name)
+            {
+                return Err(gluesql::Error::Storage(
+                    format!("create_index) index already exists: {index_name}").into(),
+                ));
+            }
+            let column_name = index::column_of(&column.expr).ok_or_else(|| {
+                gluesql::Error::Storage("create_index) only column indexes are supported".into())
+            })?;
+
+            let mut data = IndexData::default();
+            for (message, slot, row) in self.row_messages(channel_id).await.into_storage_err()? {
+                if slot.is_some() {
+                    return Err(gluesql::Error::Storage(
+                        "create_index) packed and segment rows cannot be indexed".into(),
+                    ));
+                }
+
+                let value = index::value_of(&row, schema.column_defs.as_deref(), column_name);
+                data.insert(value, message.id);
+            }
+
+            let file = self.index_file(&data).into_storage_err()?;
+            let message = self
+                .discord
+                .send_message_with_files(channel_id, index::head(index_name), vec![file])
+                .await
+                .into_storage_err()?;
+            self.discord
+                .set_pin(channel_id, message.id)
+                .await
+                .into_storage_err()?;
+            self.indexes
+                .insert(channel_id, index_name, message.id, data);
+
+            let order = match column.asc {
+                Some(true) => SchemaIndexOrd::Asc,
+                Some(false) => SchemaIndexOrd::Desc,
+                None => SchemaIndexOrd::Both,
+            };
+            schema.indexes.push(SchemaIndex {
+                name: index_name.to_owned(),
+                expr: column.expr.clone(),
+                order,
+                created: Utc::now().naive_utc(),
+            });
+
+            self.write_schema(&channel, schema).await.into_storage_err()
+        })
+    }
+
+    async fn drop_index(&mut self, table_name: &str, index_name: &str) -> gluesql::Result<()> {
+        debug::time!("drop_index", {
+            let channel_name = table_name.to_lowercase();
+            let channel = self
+                .channel(&channel_name)
+                .await
+                .into_storage_err()?
+                .ok_or_else(|| gluesql::Error::Storage("drop_index) not found channel".into()))?;
+            let channel_id = channel.id;
+
+            let mut schema = self.schema(channel.clone()).await.into_storage_err()?;
+            if !schema
+                .indexes
+                .iter()
+                .any(|schema_index| schema_index.name == index_name)
+            {
+                return Err(gluesql::Error::Storage(
+                    format!("drop_index) not found index: {index_name}").into(),
+                ));
+            }
+
+            let message = self
+                .index_message(channel_id, index_name)
+                .await
+                .into_storage_err()?;
+            self.discord
+                .delete_message(channel_id, message.id)
+                .await
+                .into_storage_err()?;
+            self.indexes.remove(channel_id, index_name);
+
+            schema
+                .indexes
+                .retain(|schema_index| schema_index.name != index_name);
+            self.write_schema(&channel, schema).await.into_storage_err()
+        })
+    }
+}
+
+/// Row messages are rewritten eagerly by `add_column` and `drop_column` before the schema
+/// message is updated, so an interrupted change can leave rows in the new layout.
+#[async_trait(?Send)]
+impl<T: Transport> AlterTable for DiscordStorage<T> {
+    async fn rename_schema(
+        &mut self,
+        table_name: &str,
+        new_table_name: &str,
+    ) -> gluesql::Result<()> {
+        debug::time!("rename_schema", {
+            let (channel, mut schema) = self.altered_table(table_name).await?;
+
+            let new_channel_name = new_table_name.to_lowercase();
+            if self
+                .channel(&new_channel_name)
+                .await
+                .into_storage_err()?
+                .is_some()
+            {
+                return Err(gluesql::Error::Storage(
+                    format!("rename_schema) table already exists: {new_table_name}").into(),
+                ));
+            }
+
+            let renamed = self
+                .discord
+                .rename_channel(channel.id, new_table_name.to_owned())
+                .await
+                .into_storage_err()?;
+            self.directory.remove(&channel.name);
+            self.directory.insert(renamed.clone());
+
+            schema.table_name = new_table_name.to_owned();
+            self.write_schema(&renamed, schema).await.into_storage_err()
+        })
+    }
+
+    async fn rename_column(
+        &mut self,
+        table_name: &str,
+        old_column_name: &str,
+        new_column_name: &str,
+    ) -> gluesql::Result<()> {
+        debug::time!("rename_column", {
+            let (channel, mut schema) = self.altered_table(table_name).await?;
+
+            let column_defs = schema
+                .column_defs
+                .as_mut()
+                .ok_or_else(|| AlterTableError::SchemalessTableFound(table_name.to_owned()))?;
+            if column_defs
+                .iter()
+                .any(|column_def| column_def.name == new_column_name)
+            {
+                return Err(
+                    AlterTableError::AlreadyExistingColumn(new_column_name.to_owned()).into(),
+                );
+            }
+
+            column_defs
+                .iter_mut()
+                .find(|column_def| column_def.name == old_column_name)
+                .ok_or(AlterTableError::RenamingColumnNotFound)?
+                .name = new_column_name.to_owned();
+
+            for schema_index in &mut schema.indexes {
+                if index::column_of(&schema_index.expr) == Some(old_column_name) {
+                    schema_index.expr = Expr::Identifier(new_column_name.to_owned());
+                }
+            }
+
+            self.write_schema(&channel, schema).await.into_storage_err()
+        })
+    }
+
+    async fn add_column(
+        &mut self,
+        table_name: &str,
+        column_def: &ColumnDef,
+    ) -> gluesql::Result<()> {
+        debug::time!("add_column", {
+            let (channel, mut schema) = self.altered_table(table_name).await?;
+
+            let column_defs = schema
+                .column_defs
+                .as_mut()
+                .ok_or_else(|| AlterTableError::SchemalessTableFound(table_name.to_owned()))?;
+            if column_defs
+                .iter()
+                .any(|existing| existing.name == column_def.name)
+            {
+                return Err(AlterTableError::AlreadyExistingColumn(column_def.name.clone()).into());
+            }
+
+            let ColumnDef {
+                data_type,
+                nullable,
+                default,
+                ..
+            } = column_def;
+            let value = match (default, nullable) {
+                (Some(expr), _) => {
+                    evaluate_stateless(None, expr)?.try_into_value(data_type, *nullable)?
+                }
+                (None, true) => Value::Null,
+                (None, false) => {
+                    return Err(AlterTableError::DefaultValueRequired(column_def.clone()).into())
+                }
+            };
+            column_defs.push(column_def.clone());
+
+            self.rewrite_rows(channel.id, |values| values.push(value.clone()))
+                .await
+                .into_storage_err()?;
+            self.write_schema(&channel, schema).await.into_storage_err()
+        })
+    }
+
+    async fn drop_column(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+        if_exists: bool,
+    ) -> gluesql::Result<()> {
+        debug::time!("drop_column", {
+            let (channel, mut schema) = self.altered_table(table_name).await?;
+            let channel_id = channel.id;
+
+            let column_defs = schema
+                .column_defs
+                .as_mut()
+                .ok_or_else(|| AlterTableError::SchemalessTableFound(table_name.to_owned()))?;
+            let position = match column_defs
+                .iter()
+                .position(|column_def| column_def.name == column_name)
+            {
+                Some(position) => position,
+                None if if_exists => return Ok(()),
+                None => {
+                    return Err(
+                        AlterTableError::DroppingColumnNotFound(column_name.to_owned()).into(),
+                    )
+                }
+            };
+            column_defs.remove(position);
+
+            // indexes on the dropped column go with it
+            for schema_index in &schema.indexes {
+                if index::column_of(&schema_index.expr) != Some(column_name) {
+                    continue;
+                }
+
+                let message = self
+                    .index_message(channel_id, &schema_index.name)
+                    .await
+                    .into_storage_err()?;
+                self.discord
+                    .delete_message(channel_id, message.id)
+                    .await
+                    .into_storage_err()?;
+                self.indexes.remove(channel_id, &schema_index.name);
+            }
+            schema
+                .indexes
+                .retain(|schema_index| index::column_of(&schema_index.expr) != Some(column_name));
+
+            self.rewrite_rows(channel_id, |values| {
+                if position < values.len() {
+                    values.remove(position);
+                }
+            })
+            .await
+            .into_storage_err()?;
+            self.keys.remove(channel_id);
+
+            self.write_schema(&channel, schema).await.into_storage_err()
+        })
+    }
+}
+
+/// Statements outside `BEGIN` are written directly. Inside a transaction row writes are
+/// buffered and read back by `fetch_data`/`scan_data`, schema changes are still applied directly.
+///
+/// Discord has no atomic batch, so `commit` is best effort: tables are written one after
+/// another and the first failure stops it with [`StorageError::CommitFailed`], listing the
+/// tables written completely. The failed table may be written partially, the rest not at all.
+#[async_trait(?Send)]
+impl<T: Transport> Transaction for DiscordStorage<T> {
+    async fn begin(&mut self, autocommit: bool) -> gluesql::Result<bool> {
+        if autocommit {
+            return Ok(false);
+        }
+
+        if self.transaction.is_some() {
+            return Err(gluesql::Error::Storage(
+                "begin) nested transaction is not supported".into(),
+            ));
+        }
+
+        self.transaction = Some(WriteSet::default());
+        Ok(false)
+    }
+
+    async fn rollback(&mut self) -> gluesql::Result<()> {
+        self.transaction = None;
+
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> gluesql::Result<()> {
+        debug::time!("commit", {
+            let write_set = match self.transaction.take() {
+                Some(write_set) => write_set,
+                None => return Ok(()),
+            };
+
+            let mut committed = Vec::new();
+            for (table_name, writes) in write_set.into_tables() {
+                if let Err(err) = self.apply_writes(&table_name, writes).await {
+                    return Err(StorageError::CommitFailed {
+                        committed,
+                        table_name,
+                        reason: err.to_string(),
+                    }
+                    .into());
+                }
+
+                committed.push(table_name);
+            }
+
+            self.flush().await
+        })
+    }
+}
+
+impl<T: Transport> Drop for DiscordStorage<T> {
+    /// Uploads the rows still waiting in the segment buffer. Dropping can't await, so the
+    /// upload blocks the thread, which a current thread runtime does not allow: the rows are
+    /// then logged as lost, call [`DiscordStorage::flush`] before dropping the storage there.
+    fn drop(&mut self) {
+        if self.segment_buffer.is_empty() {
+            return;
+        }
+
+        let flushed = match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                task::block_in_place(|| handle.block_on(self.flush()))
+            }
+            Ok(_) => Err(gluesql::Error::Storage(
+                "drop) can't block a current thread runtime".into(),
+            )),
+            Err(_) => runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|err| gluesql::Error::Storage(err.into()))
+                .and_then(|runtime| runtime.block_on(self.flush())),
+        };
+
+        if let Err(err) = flushed {
+            tracing::error!("drop) buffered segment rows lost: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql_core::{
+        ast::{ColumnDef, ColumnUniqueOption, DataType, Expr, IndexOperator, OrderByExpr},
+        chrono::{Duration, Utc},
+        data::Schema,
+        prelude::{Key, Value},
+        store::{
+            AlterTable, AlterTableError, DataRow, Index, IndexMut, Store, StoreMut, Transaction,
+        },
+    };
+
+    use super::{
+        ColumnStats, DeleteStats, DiscordStorage, EncryptionKey, RowCodec, StorageError,
+        StorageOptions,
+    };
+    use serenity::{
+        futures::{StreamExt, TryStreamExt},
+        model::prelude::{MessageId, MessageType, ReactionType, UserId},
+    };
+
+    use crate::transport::{
+        fake::{FakeDiscord, FakeGuild},
+        Transport,
+    };
+
+    fn schema(table_name: &str) -> Schema {
+        Schema {
+            table_name: table_name.to_owned(),
+            column_defs: Some(vec![
+                ColumnDef {
+                    name: "id".to_owned(),
+                    data_type: DataType::Int,
+                    nullable: false,
+                    default: None,
+                    unique: None,
+                },
+                ColumnDef {
+                    name: "name".to_owned(),
+                    data_type: DataType::Text,
+                    nullable: true,
+                    default: None,
+                    unique: None,
+                },
+            ]),
+            indexes: vec![],
+            engine: None,
+            created: Utc::now().naive_utc(),
+        }
+    }
+
+    fn row(id: i64, name: &str) -> DataRow {
+        DataRow::Vec(vec![Value::I64(id), Value::Str(name.to_owned())])
+    }
+
+    async fn rows(storage: &impl Store, table_name: &str) -> Vec<(Key, DataRow)> {
+        storage
+            .scan_data(table_name)
+            .await
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    async fn indexed(
+        storage: &impl Index,
+        asc: Option<bool>,
+        cmp_value: Option<(&IndexOperator, Value)>,
+    ) -> Vec<DataRow> {
+        storage
+            .scan_indexed_data("User", "idx_id", asc, cmp_value)
+            .await
+            .unwrap()
+            .map(|row| row.unwrap().1)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn schema_roundtrip() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        let fetched = storage.fetch_schema("User").await.unwrap().unwrap();
+        assert_eq!(fetched.table_name, "User");
+        assert_eq!(fetched.column_defs, schema("User").column_defs);
+        assert_eq!(storage.fetch_all_schemas().await.unwrap().len(), 1);
+
+        assert!(storage.insert_schema(&schema("User")).await.is_err());
+
+        storage.delete_schema("User").await.unwrap();
+        assert!(storage.fetch_schema("User").await.unwrap().is_none());
+        assert!(discord.guild().channels().is_empty());
+    }
+
+    #[tokio::test]
+    async fn append_scan_in_insertion_order() {
+        let mut storage = FakeDiscord::new("test").into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        storage
+            .append_data("User", vec![row(1, "glue"), row(2, "sql")])
+            .await
+            .unwrap();
+
+        let rows = rows(&storage, "User").await;
+        assert_eq!(
+            rows.into_iter().map(|(_, row)| row).collect::<Vec<_>>(),
+            vec![row(1, "glue"), row(2, "sql")]
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_stream_pages_lazily() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        let appended = (0..250).map(|id| row(id, "glue")).collect::<Vec<_>>();
+        storage.append_data("User", appended.clone()).await.unwrap();
+
+        let first = storage
+            .scan_stream("User")
+            .await
+            .unwrap()
+            .take(1)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(first[0].1, row(0, "glue"));
+        assert_eq!(discord.calls("messages_page"), 1);
+
+        let scanned = rows(&storage, "User").await;
+        assert_eq!(
+            scanned.into_iter().map(|(_, row)| row).collect::<Vec<_>>(),
+            appended
+        );
+        assert_eq!(discord.calls("messages_page"), 4);
+        assert_eq!(discord.calls("latest_message_stream"), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scan_data_pages_lazily_on_multi_thread_runtime() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        let appended = (0..250).map(|id| row(id, "glue")).collect::<Vec<_>>();
+        storage.append_data("User", appended.clone()).await.unwrap();
+
+        let mut scanned = storage.scan_data("User").await.unwrap();
+        assert_eq!(discord.calls("messages_page"), 0);
+        assert_eq!(scanned.next().unwrap().unwrap().1, row(0, "glue"));
+        assert_eq!(discord.calls("messages_page"), 1);
+
+        let rest = scanned.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(rest.len(), 249);
+        assert_eq!(discord.calls("messages_page"), 3);
+
+        // the scan counted every row, so deleting them all recreates the channel
+        let keys = rows(&storage, "User")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        storage.delete_data("User", keys).await.unwrap();
+        assert_eq!(discord.calls("delete_channel"), 1);
+        assert!(rows(&storage, "User").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn scan_range_reads_only_the_slice() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        discord.guild_mut().backdate(Duration::days(2));
+        storage
+            .append_data("User", (0..3).map(|id| row(id, "old")).collect())
+            .await
+            .unwrap();
+        discord.guild_mut().backdate(Duration::zero());
+        storage
+            .append_data("User", (3..250).map(|id| row(id, "new")).collect())
+            .await
+            .unwrap();
+
+        let scanned = rows(&storage, "User").await;
+        let pages = discord.calls("messages_page");
+        let sliced = storage
+            .scan_range("User", Some(&scanned[200].0), Some(&scanned[210].0))
+            .await
+            .unwrap();
+        assert_eq!(sliced, scanned[200..210]);
+        assert_eq!(discord.calls("messages_page"), pages + 1);
+
+        let recent = storage
+            .scan_time_range("User", Some(Utc::now() - Duration::days(1)), None)
+            .await
+            .unwrap();
+        assert_eq!(recent.len(), 247);
+        assert_eq!(recent[0].1, row(3, "new"));
+
+        let mut schema = schema("Item");
+        schema.column_defs.as_mut().unwrap()[0].unique =
+            Some(ColumnUniqueOption { is_primary: true });
+        storage.insert_schema(&schema).await.unwrap();
+        assert!(storage.scan_range("Item", None, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn update_and_delete_by_key() {
+        let mut storage = FakeDiscord::new("test").into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", vec![row(1, "glue"), row(2, "sql")])
+            .await
+            .unwrap();
+
+        let keys = rows(&storage, "User")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+
+        storage
+            .insert_data("User", vec![(keys[0].clone(), row(1, "GLUE"))])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_data("User", &keys[0]).await.unwrap(),
+            Some(row(1, "GLUE"))
+        );
+
+        storage
+            .delete_data("User", vec![keys[1].clone()])
+            .await
+            .unwrap();
+        assert_eq!(storage.fetch_data("User", &keys[1]).await.unwrap(), None);
+        assert_eq!(rows(&storage, "User").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn update_of_deleted_row_keeps_key() {
+        let mut storage = FakeDiscord::new("test").into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", vec![row(1, "glue")])
+            .await
+            .unwrap();
+
+        let key = rows(&storage, "User").await.remove(0).0;
+        storage
+            .delete_data("User", vec![key.clone()])
+            .await
+            .unwrap();
+
+        let err = storage
+            .insert_data("User", vec![(key.clone(), row(1, "GLUE"))])
+            .await
+            .unwrap_err();
+        match err {
+            gluesql_core::result::Error::Storage(err) => assert!(matches!(
+                err.downcast_ref::<StorageError>(),
+                Some(StorageError::RowNotFound { key: missing, .. }) if missing == &key
+            )),
+            err => panic!("unexpected error: {err}"),
+        }
+        assert!(rows(&storage, "User").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_lookup_is_not_a_missing_row() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", vec![row(1, "glue")])
+            .await
+            .unwrap();
+        let key = rows(&storage, "User").await.remove(0).0;
+
+        discord.set_rate_limited(true);
+        match storage
+            .insert_data("User", vec![(key.clone(), row(1, "GLUE"))])
+            .await
+            .unwrap_err()
+        {
+            gluesql_core::result::Error::Storage(err) => {
+                assert!(err.downcast_ref::<StorageError>().is_none());
+                assert!(err.to_string().contains("rate limited"));
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+        assert!(storage.fetch_data("User", &key).await.is_err());
+
+        discord.set_rate_limited(false);
+        assert_eq!(
+            storage.fetch_data("User", &key).await.unwrap(),
+            Some(row(1, "glue"))
+        );
+    }
+
+    #[tokio::test]
+    async fn channel_lookups_are_cached() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", vec![row(1, "glue")])
+            .await
+            .unwrap();
+        storage
+            .append_data("User", vec![row(2, "sql")])
+            .await
+            .unwrap();
+        rows(&storage, "User").await;
+        assert_eq!(discord.calls("get_channels"), 1);
+
+        let mut builder = serenity::builder::CreateChannel::default();
+        builder.name("other");
+        discord
+            .create_channel(discord.guild_id(), builder)
+            .await
+            .unwrap();
+        assert!(storage.fetch_schema("other").await.unwrap().is_some());
+        assert_eq!(discord.calls("get_channels"), 2);
+
+        storage.invalidate_channel_cache();
+        assert!(storage.fetch_schema("User").await.unwrap().is_some());
+        assert_eq!(discord.calls("get_channels"), 3);
+    }
+
+    #[tokio::test]
+    async fn schemas_are_cached() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+
+        storage.insert_schema(&schema("User")).await.unwrap();
+        let pins = discord.calls("get_pins");
+
+        storage.fetch_schema("User").await.unwrap().unwrap();
+        storage.fetch_all_schemas().await.unwrap();
+        assert_eq!(discord.calls("get_pins"), pins);
+
+        storage.invalidate_schema_cache();
+        storage.fetch_schema("User").await.unwrap().unwrap();
+        assert_eq!(discord.calls("get_pins"), pins + 1);
+
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "user")
+            .await
+            .unwrap()
+            .unwrap();
+        let schema_message = discord.get_pins(channel_id).await.unwrap().remove(0);
+        discord
+            .set_unpin(channel_id, schema_message.id)
+            .await
+            .unwrap();
+        storage.invalidate_channel_cache();
+        assert!(storage
+            .fetch_schema("User")
+            .await
+            .unwrap()
+            .unwrap()
+            .column_defs
+            .is_none());
+
+        storage.delete_schema("User").await.unwrap();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        assert!(storage
+            .fetch_schema("User")
+            .await
+            .unwrap()
+            .unwrap()
+            .column_defs
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn primary_key_upsert_and_fetch() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+
+        let mut schema = schema("User");
+        schema.column_defs.as_mut().unwrap()[0].unique =
+            Some(ColumnUniqueOption { is_primary: true });
+        storage.insert_schema(&schema).await.unwrap();
+
+        storage
+            .insert_data(
+                "User",
+                vec![(Key::I64(2), row(2, "sql")), (Key::I64(1), row(1, "glue"))],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            rows(&storage, "User").await,
+            vec![(Key::I64(1), row(1, "glue")), (Key::I64(2), row(2, "sql"))]
+        );
+
+        storage
+            .insert_data("User", vec![(Key::I64(2), row(2, "SQL"))])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_data("User", &Key::I64(2)).await.unwrap(),
+            Some(row(2, "SQL"))
+        );
+        assert_eq!(
+            storage.fetch_data("User", &Key::I64(3)).await.unwrap(),
+            None
+        );
+
+        storage
+            .delete_data("User", vec![Key::I64(1)])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_data("User", &Key::I64(1)).await.unwrap(),
+            None
+        );
+
+        let mut restarted = discord.into_storage();
+        assert_eq!(
+            rows(&restarted, "User").await,
+            vec![(Key::I64(2), row(2, "SQL"))]
+        );
+        restarted
+            .insert_data("User", vec![(Key::I64(2), row(2, "GlueSQL"))])
+            .await
+            .unwrap();
+        assert_eq!(rows(&restarted, "User").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn primary_key_map_is_stored() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+
+        let mut schema = schema("User");
+        schema.column_defs.as_mut().unwrap()[0].unique =
+            Some(ColumnUniqueOption { is_primary: true });
+        storage.insert_schema(&schema).await.unwrap();
+        storage
+            .insert_data(
+                "User",
+                (1..=3).map(|id| (Key::I64(id), row(id, "glue"))).collect(),
+            )
+            .await
+            .unwrap();
+
+        // a restarted storage reads the pinned key map instead of the channel history
+        let pages = discord.calls("messages_page");
+        let restarted = discord.clone().into_storage();
+        assert_eq!(
+            restarted.fetch_data("User", &Key::I64(2)).await.unwrap(),
+            Some(row(2, "glue"))
+        );
+        assert_eq!(
+            restarted.fetch_data("User", &Key::I64(4)).await.unwrap(),
+            None
+        );
+        assert_eq!(discord.calls("messages_page"), pages);
+    }
+
+    #[tokio::test]
+    async fn primary_key_miss_reads_the_key_map_again() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+
+        let mut schema = schema("User");
+        schema.column_defs.as_mut().unwrap()[0].unique =
+            Some(ColumnUniqueOption { is_primary: true });
+        storage.insert_schema(&schema).await.unwrap();
+        storage
+            .insert_data("User", vec![(Key::I64(1), row(1, "glue"))])
+            .await
+            .unwrap();
+
+        // another writer adds a row after `storage` loaded its key map
+        let mut other = discord.clone().into_storage();
+        other
+            .insert_data("User", vec![(Key::I64(2), row(2, "sql"))])
+            .await
+            .unwrap();
+
+        storage
+            .insert_data("User", vec![(Key::I64(2), row(2, "SQL"))])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows(&storage, "User").await,
+            vec![(Key::I64(1), row(1, "glue")), (Key::I64(2), row(2, "SQL"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn rows_over_the_content_limit_are_chunked() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        let large = row(1, &"glue".repeat(1200));
+        storage
+            .append_data("User", vec![large.clone(), row(2, "sql")])
+            .await
+            .unwrap();
+
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "user")
+            .await
+            .unwrap()
+            .unwrap();
+        let messages = discord.guild().messages(channel_id).unwrap().len();
+
+        let scanned = rows(&storage, "User").await;
+        assert_eq!(
+            scanned
+                .iter()
+                .map(|(_, row)| row.clone())
+                .collect::<Vec<_>>(),
+            vec![large, row(2, "sql")]
+        );
+        let key = scanned[0].0.clone();
+
+        let larger = row(1, &"GLUE".repeat(2400));
+        storage
+            .insert_data("User", vec![(key.clone(), larger.clone())])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_data("User", &key).await.unwrap(),
+            Some(larger)
+        );
+        assert_eq!(
+            discord.guild().messages(channel_id).unwrap().len(),
+            messages + 2
+        );
+
+        storage.delete_data("User", vec![key]).await.unwrap();
+        assert_eq!(rows(&storage, "User").await.len(), 1);
+        assert_eq!(
+            discord.guild().messages(channel_id).unwrap().len(),
+            messages - 4
+        );
+    }
+
+    #[tokio::test]
+    async fn attachment_backed_rows() {
+        let discord = FakeDiscord::new("test");
+        let options = StorageOptions {
+            attachment_threshold: Some(200),
+            bytea_attachments: true,
+            ..StorageOptions::default()
+        };
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        let bytea = DataRow::Vec(vec![Value::I64(1), Value::Bytea(vec![0, 1, 255])]);
+        let large = row(2, &"sql".repeat(100));
+        storage
+            .append_data("User", vec![bytea.clone(), large.clone()])
+            .await
+            .unwrap();
+
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "user")
+            .await
+            .unwrap()
+            .unwrap();
+        let messages = discord.guild().messages(channel_id).unwrap();
+        assert_eq!(messages[0].content, "<attachment:row.json>");
+        assert_eq!(messages[1].attachments[0].filename, "bytea-0.bin");
+
+        let scanned = rows(&storage, "User").await;
+        assert_eq!(
+            scanned
+                .iter()
+                .map(|(_, row)| row.clone())
+                .collect::<Vec<_>>(),
+            vec![bytea, large]
+        );
+
+        let key = scanned[1].0.clone();
+        storage
+            .insert_data("User", vec![(key.clone(), row(2, "sql"))])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_data("User", &key).await.unwrap(),
+            Some(row(2, "sql"))
+        );
+        assert!(discord.guild().messages(channel_id).unwrap()[0]
+            .attachments
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn tables_mix_row_codecs() {
+        let discord = FakeDiscord::new("test");
+        let mut pretty = discord.clone().into_storage();
+        pretty.insert_schema(&schema("User")).await.unwrap();
+        pretty
+            .append_data("User", vec![row(1, "glue")])
+            .await
+            .unwrap();
+
+        let options = StorageOptions {
+            row_codec: RowCodec::Compressed,
+            ..StorageOptions::default()
+        };
+        let mut compressed =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        compressed
+            .append_data("User", vec![row(2, &"sql".repeat(1000))])
+            .await
+            .unwrap();
+
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "user")
+            .await
+            .unwrap()
+            .unwrap();
+        let latest = discord.guild().messages(channel_id).unwrap().remove(0);
+        assert!(latest.content.starts_with("```deflate"));
+        assert!(latest.content.len() < 2000);
+
+        for storage in [&pretty, &compressed] {
+            assert_eq!(
+                rows(storage, "User")
+                    .await
+                    .into_iter()
+                    .map(|(_, row)| row)
+                    .collect::<Vec<_>>(),
+                vec![row(1, "glue"), row(2, &"sql".repeat(1000))]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypted_rows_and_schemas() {
+        let discord = FakeDiscord::new("test");
+        let encrypted = |key: u8| StorageOptions {
+            bytea_attachments: true,
+            encryption_key: Some(EncryptionKey::new([key; 32])),
+            encrypt_schemas: true,
+            ..StorageOptions::default()
+        };
+
+        let mut plain = discord.clone().into_storage();
+        plain.insert_schema(&schema("User")).await.unwrap();
+        plain
+            .append_data("User", vec![row(1, "glue")])
+            .await
+            .unwrap();
+
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), encrypted(1));
+        let bytea = DataRow::Vec(vec![Value::I64(2), Value::Bytea(vec![0, 1, 255])]);
+        storage
+            .append_data("User", vec![bytea.clone()])
+            .await
+            .unwrap();
+        storage.insert_schema(&schema("Secret")).await.unwrap();
+
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "user")
+            .await
+            .unwrap()
+            .unwrap();
+        let latest = discord.guild().messages(channel_id).unwrap().remove(0);
+        assert!(latest.content.starts_with("```enc"));
+        assert_ne!(
+            discord
+                .guild()
+                .attachment(latest.attachments[0].id)
+                .unwrap(),
+            [0, 1, 255]
+        );
+
+        assert_eq!(
+            rows(&storage, "User")
+                .await
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect::<Vec<_>>(),
+            vec![row(1, "glue"), bytea]
+        );
+        assert_eq!(
+            storage
+                .fetch_schema("Secret")
+                .await
+                .unwrap()
+                .unwrap()
+                .column_defs,
+            schema("Secret").column_defs
+        );
+
+        let storage_error = |err| match err {
+            gluesql_core::result::Error::Storage(err) => err,
+            err => panic!("unexpected error: {err}"),
+        };
+
+        plain.invalidate_schema_cache();
+        let err = storage_error(plain.scan_data("User").await.map(|_| ()).unwrap_err());
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::MissingEncryptionKey { .. })
+        ));
+        let err = storage_error(plain.fetch_schema("Secret").await.unwrap_err());
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::MissingEncryptionKey { .. })
+        ));
+
+        let wrong_key =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), encrypted(2));
+        let err = storage_error(wrong_key.scan_data("User").await.map(|_| ()).unwrap_err());
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::Decryption { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn transaction_buffers_until_commit() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", vec![row(1, "glue")])
+            .await
+            .unwrap();
+        let key = rows(&storage, "User").await.remove(0).0;
+        let sent = discord.calls("send_message");
+
+        storage.begin(false).await.unwrap();
+        assert!(storage.begin(false).await.is_err());
+        storage
+            .append_data("User", vec![row(2, "sql")])
+            .await
+            .unwrap();
+        storage
+            .insert_data("User", vec![(key.clone(), row(1, "GLUE"))])
+            .await
+            .unwrap();
+        assert_eq!(discord.calls("send_message"), sent);
+        assert_eq!(
+            storage.fetch_data("User", &key).await.unwrap(),
+            Some(row(1, "GLUE"))
+        );
+
+        let pending = rows(&storage, "User").await;
+        assert_eq!(
+            pending
+                .iter()
+                .map(|(_, row)| row.clone())
+                .collect::<Vec<_>>(),
+            vec![row(1, "GLUE"), row(2, "sql")]
+        );
+        storage
+            .delete_data("User", vec![pending[1].0.clone()])
+            .await
+            .unwrap();
+        assert_eq!(rows(&storage, "User").await.len(), 1);
+
+        storage.rollback().await.unwrap();
+        assert_eq!(
+            rows(&storage, "User").await,
+            vec![(key.clone(), row(1, "glue"))]
+        );
+
+        storage.begin(false).await.unwrap();
+        storage
+            .append_data("User", vec![row(2, "sql")])
+            .await
+            .unwrap();
+        storage
+            .delete_data("User", vec![key.clone()])
+            .await
+            .unwrap();
+        storage.commit().await.unwrap();
+        assert_eq!(
+            rows(&storage, "User")
+                .await
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect::<Vec<_>>(),
+            vec![row(2, "sql")]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_commit_reports_written_tables() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage.insert_schema(&schema("Item")).await.unwrap();
+
+        storage.begin(false).await.unwrap();
+        storage
+            .append_data("User", vec![row(1, "glue")])
+            .await
+            .unwrap();
+        storage
+            .append_data("Item", vec![row(1, "sql")])
+            .await
+            .unwrap();
+
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "item")
+            .await
+            .unwrap()
+            .unwrap();
+        discord.delete_channel(channel_id).await.unwrap();
+        storage.invalidate_channel_cache();
+
+        match storage.commit().await.unwrap_err() {
+            gluesql_core::result::Error::Storage(err) => assert!(matches!(
+                err.downcast_ref::<StorageError>(),
+                Some(StorageError::CommitFailed { committed, table_name, .. })
+                    if committed == &["user"] && table_name == "item"
+            )),
+            err => panic!("unexpected error: {err}"),
+        }
+        assert_eq!(rows(&storage, "User").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_keep_row_order() {
+        let options = StorageOptions {
+            write_concurrency: 4,
+            ..StorageOptions::default()
+        };
+        let mut keyed = schema("User");
+        keyed.column_defs.as_mut().unwrap()[0].unique =
+            Some(ColumnUniqueOption { is_primary: true });
+
+        let discord = FakeDiscord::new("test");
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options.clone());
+        storage.insert_schema(&schema("Log")).await.unwrap();
+        storage.insert_schema(&keyed).await.unwrap();
+
+        discord.set_overtaking_writes(true);
+        storage
+            .append_data("Log", (1..=8).map(|id| row(id, "log")).collect())
+            .await
+            .unwrap();
+        // rows without a primary key are sent one at a time and never moved afterwards
+        assert_eq!(discord.max_concurrent_writes(), 1);
+        assert_eq!(discord.calls("edit_message"), 0);
+        assert_eq!(
+            rows(&storage, "Log")
+                .await
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect::<Vec<_>>(),
+            (1..=8).map(|id| row(id, "log")).collect::<Vec<_>>()
+        );
+
+        storage
+            .insert_data(
+                "User",
+                (1..=8)
+                    .rev()
+                    .map(|id| (Key::I64(id), row(id, "glue")))
+                    .collect(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(discord.max_concurrent_writes(), 4);
+        assert_eq!(
+            rows(&storage, "User").await,
+            (1..=8)
+                .map(|id| (Key::I64(id), row(id, "glue")))
+                .collect::<Vec<_>>()
+        );
+
+        let discord = FakeDiscord::new("test");
+        discord.set_send_capacity(Some(2));
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        storage.insert_schema(&keyed).await.unwrap();
+        storage
+            .append_data("User", (1..=8).map(|id| row(id, "sql")).collect())
+            .await
+            .unwrap();
+        assert_eq!(discord.max_concurrent_writes(), 2);
+        assert_eq!(rows(&storage, "User").await.len(), 8);
+    }
+
+    #[tokio::test]
+    async fn packed_rows_share_messages() {
+        let options = StorageOptions {
+            pack_rows: true,
+            ..StorageOptions::default()
+        };
+        let discord = FakeDiscord::new("test");
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        storage.insert_schema(&schema("Log")).await.unwrap();
+
+        storage
+            .append_data("Log", (1..=3).map(|id| row(id, "glue")).collect())
+            .await
+            .unwrap();
+        storage
+            .append_data("Log", vec![row(4, "sql")])
+            .await
+            .unwrap();
+        assert_eq!(discord.calls("send_message"), 2);
+        assert_eq!(discord.calls("edit_message"), 1);
+
+        let (keys, stored): (Vec<_>, Vec<_>) = rows(&storage, "Log").await.into_iter().unzip();
+        assert_eq!(
+            stored,
+            vec![
+                row(1, "glue"),
+                row(2, "glue"),
+                row(3, "glue"),
+                row(4, "sql")
+            ]
+        );
+        assert!(matches!(&keys[1], Key::Str(key) if key.ends_with(":1")));
+
+        storage
+            .insert_data("Log", vec![(keys[1].clone(), row(2, "SQL"))])
+            .await
+            .unwrap();
+        storage
+            .delete_data("Log", vec![keys[0].clone()])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_data("Log", &keys[1]).await.unwrap(),
+            Some(row(2, "SQL"))
+        );
+        assert_eq!(storage.fetch_data("Log", &keys[0]).await.unwrap(), None);
+        assert_eq!(
+            storage.fetch_data("Log", &keys[3]).await.unwrap(),
+            Some(row(4, "sql"))
+        );
+        assert!(storage
+            .insert_data("Log", vec![(keys[0].clone(), row(1, "glue"))])
+            .await
+            .is_err());
+
+        storage
+            .append_data("Log", (5..=60).map(|id| row(id, "packed")).collect())
+            .await
+            .unwrap();
+        let keys = rows(&storage, "Log")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys.len(), 59);
+        assert!(discord.calls("send_message") > 2);
+
+        storage.delete_data("Log", keys).await.unwrap();
+        assert_eq!(discord.calls("delete_channel"), 1);
+        assert!(rows(&storage, "Log").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn segments_hold_rows_and_stats() {
+        let options = StorageOptions {
+            segment_rows: Some(4),
+            pack_rows: true,
+            ..StorageOptions::default()
+        };
+        let discord = FakeDiscord::new("test");
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        storage.insert_schema(&schema("Metric")).await.unwrap();
+
+        storage
+            .append_data("Metric", (1..=6).map(|id| row(id, "cpu")).collect())
+            .await
+            .unwrap();
+        storage
+            .append_data("Metric", (7..=10).map(|id| row(id, "cpu")).collect())
+            .await
+            .unwrap();
+        // rows wait until they fill a segment, the rest is uploaded by flush
+        assert_eq!(discord.calls("send_message_with_files"), 2);
+        storage.flush().await.unwrap();
+        assert_eq!(discord.calls("send_message_with_files"), 3);
+        storage.flush().await.unwrap();
+        assert_eq!(discord.calls("send_message_with_files"), 3);
+
+        let (keys, stored): (Vec<_>, Vec<_>) = rows(&storage, "Metric").await.into_iter().unzip();
+        assert_eq!(
+            stored,
+            (1..=10).map(|id| row(id, "cpu")).collect::<Vec<_>>()
+        );
+
+        let stats = storage.segment_stats("Metric").await.unwrap();
+        assert_eq!(
+            stats.iter().map(|stats| stats.rows).collect::<Vec<_>>(),
+            vec![4, 4, 2]
+        );
+        assert_eq!(
+            stats[1].columns[0],
+            Some(ColumnStats {
+                min: Value::I64(5),
+                max: Value::I64(8),
+            })
+        );
+        assert!(stats[0].excludes(0, &IndexOperator::Gt, &Value::I64(4)));
+
+        // only the segment of rows 5 to 8 is downloaded
+        let downloads = discord.calls("download_attachment");
+        assert_eq!(
+            storage
+                .scan_filtered("Metric", "id", &IndexOperator::Eq, &Value::I64(6))
+                .await
+                .unwrap(),
+            vec![(keys[5].clone(), row(6, "cpu"))]
+        );
+        assert_eq!(discord.calls("download_attachment"), downloads + 1);
+        assert!(storage
+            .scan_filtered("Metric", "size", &IndexOperator::Eq, &Value::I64(6))
+            .await
+            .is_err());
+
+        storage
+            .insert_data("Metric", vec![(keys[4].clone(), row(50, "gpu"))])
+            .await
+            .unwrap();
+        storage
+            .delete_data(
+                "Metric",
+                vec![keys[5].clone(), keys[8].clone(), keys[9].clone()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_data("Metric", &keys[4]).await.unwrap(),
+            Some(row(50, "gpu"))
+        );
+        assert_eq!(storage.fetch_data("Metric", &keys[5]).await.unwrap(), None);
+        assert_eq!(rows(&storage, "Metric").await.len(), 7);
+
+        let stats = storage.segment_stats("Metric").await.unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats[1].columns[0],
+            Some(ColumnStats {
+                min: Value::I64(7),
+                max: Value::I64(50),
+            })
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn segment_buffer_is_flushed_on_commit_and_drop() {
+        let options = StorageOptions {
+            segment_rows: Some(4),
+            ..StorageOptions::default()
+        };
+        let discord = FakeDiscord::new("test");
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options.clone());
+        storage.insert_schema(&schema("Metric")).await.unwrap();
+
+        storage.begin(false).await.unwrap();
+        storage
+            .append_data("Metric", (1..=3).map(|id| row(id, "cpu")).collect())
+            .await
+            .unwrap();
+        storage.commit().await.unwrap();
+        assert_eq!(discord.calls("send_message_with_files"), 1);
+
+        storage
+            .append_data("Metric", (4..=5).map(|id| row(id, "cpu")).collect())
+            .await
+            .unwrap();
+        assert_eq!(discord.calls("send_message_with_files"), 1);
+        drop(storage);
+        assert_eq!(discord.calls("send_message_with_files"), 2);
+
+        let storage = DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        assert_eq!(
+            rows(&storage, "Metric")
+                .await
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect::<Vec<_>>(),
+            (1..=5).map(|id| row(id, "cpu")).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_data_bulk_deletes_young_messages() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        discord.guild_mut().backdate(Duration::days(15));
+        storage
+            .append_data("User", (1..=3).map(|id| row(id, "old")).collect())
+            .await
+            .unwrap();
+        discord.guild_mut().backdate(Duration::zero());
+        storage
+            .append_data("User", (4..=6).map(|id| row(id, "new")).collect())
+            .await
+            .unwrap();
+        storage
+            .append_data("User", vec![row(7, &"long".repeat(1000))])
+            .await
+            .unwrap();
+
+        let keys = rows(&storage, "User")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        let fetches = discord.calls("get_message");
+        storage.delete_data("User", keys).await.unwrap();
+
+        // the scan listed the chunks of row 7, so no row message is fetched again
+        assert_eq!(discord.calls("get_message"), fetches);
+        assert!(rows(&storage, "User").await.is_empty());
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "user")
+            .await
+            .unwrap()
+            .unwrap();
+        let messages = discord.guild().messages(channel_id).unwrap();
+        assert!(messages
+            .iter()
+            .all(|message| message.pinned || !matches!(message.kind, MessageType::Regular)));
+        // row 7 is bulk deleted with its continuation messages
+        assert!(matches!(
+            storage.delete_stats(),
+            DeleteStats { bulk, single: 3 } if bulk > 4
+        ));
+        assert_eq!(discord.calls("delete_messages"), 1);
+        assert_eq!(discord.calls("delete_message"), 3);
+    }
+
+    #[tokio::test]
+    async fn indexed_scans() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", vec![row(3, "c"), row(1, "a"), row(2, "b")])
+            .await
+            .unwrap();
+
+        let column = OrderByExpr {
+            expr: Expr::Identifier("id".to_owned()),
+            asc: None,
+        };
+        storage
+            .create_index("User", "idx_id", &column)
+            .await
+            .unwrap();
+        assert!(storage
+            .create_index("User", "idx_id", &column)
+            .await
+            .is_err());
+        storage
+            .append_data("User", vec![row(4, "d")])
+            .await
+            .unwrap();
+
+        let streams = discord.calls("latest_message_stream");
+        assert_eq!(
+            indexed(&storage, None, None).await,
+            vec![row(1, "a"), row(2, "b"), row(3, "c"), row(4, "d")]
+        );
+        assert_eq!(
+            indexed(
+                &storage,
+                Some(false),
+                Some((&IndexOperator::Gt, Value::I64(2)))
+            )
+            .await,
+            vec![row(4, "d"), row(3, "c")]
+        );
+        assert_eq!(discord.calls("latest_message_stream"), streams);
+
+        let key = rows(&storage, "User").await.remove(0).0;
+        storage
+            .insert_data("User", vec![(key.clone(), row(5, "e"))])
+            .await
+            .unwrap();
+        assert_eq!(
+            indexed(&storage, None, Some((&IndexOperator::GtEq, Value::I64(3)))).await,
+            vec![row(4, "d"), row(5, "e")]
+        );
+
+        let restarted = discord.clone().into_storage();
+        let fetched = restarted.fetch_schema("User").await.unwrap().unwrap();
+        assert_eq!(fetched.column_defs, schema("User").column_defs);
+        assert_eq!(fetched.indexes[0].name, "idx_id");
+        assert_eq!(
+            indexed(&restarted, None, Some((&IndexOperator::Eq, Value::I64(5)))).await,
+            vec![row(5, "e")]
+        );
+
+        storage.delete_data("User", vec![key]).await.unwrap();
+        assert_eq!(indexed(&storage, None, None).await.len(), 3);
+
+        storage.drop_index("User", "idx_id").await.unwrap();
+        assert!(storage
+            .fetch_schema("User")
+            .await
+            .unwrap()
+            .unwrap()
+            .indexes
+            .is_empty());
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "user")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(discord.get_pins(channel_id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn truncate_recreates_channel() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", (1..=12).map(|id| row(id, "glue")).collect())
+            .await
+            .unwrap();
+
+        let channel = |discord: FakeDiscord| async move {
+            let channel_id = discord
+                .get_channel_id(discord.guild_id(), "user")
+                .await
+                .unwrap()
+                .unwrap();
+
+            discord.get_channels(discord.guild_id()).await.unwrap()[&channel_id].clone()
+        };
+        let before = channel(discord.clone()).await;
+        let human = FakeGuild::user(UserId(42), "glue", false);
+        let note = discord
+            .guild_mut()
+            .send_message_as(before.id, &human, "read me")
+            .unwrap();
+        discord.set_pin(before.id, note.id).await.unwrap();
+
+        // every row but one keeps the channel
+        let mut keys = rows(&storage, "User")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        let kept = keys.pop().unwrap();
+        let bulk_deletes = discord.calls("delete_messages");
+        storage.delete_data("User", keys).await.unwrap();
+        assert_eq!(channel(discord.clone()).await.id, before.id);
+        assert_eq!(discord.calls("delete_messages"), bulk_deletes + 1);
+        assert_eq!(rows(&storage, "User").await.len(), 1);
+
+        // a row sent after the scan keeps the channel too
+        storage
+            .append_data("User", (13..=24).map(|id| row(id, "sql")).collect())
+            .await
+            .unwrap();
+        let keys = rows(&storage, "User")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert!(keys.contains(&kept));
+        let mut other = discord.clone().into_storage();
+        other
+            .append_data("User", vec![row(25, "late")])
+            .await
+            .unwrap();
+        storage.delete_data("User", keys).await.unwrap();
+        assert_eq!(channel(discord.clone()).await.id, before.id);
+        assert_eq!(rows(&storage, "User").await.len(), 1);
+
+        let keys = rows(&storage, "User")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        storage.delete_data("User", keys).await.unwrap();
+        assert_eq!(channel(discord.clone()).await.id, before.id);
+        assert!(rows(&storage, "User").await.is_empty());
+
+        // deleting every row of the last scan recreates the channel
+        storage
+            .append_data("User", (26..=37).map(|id| row(id, "glue")).collect())
+            .await
+            .unwrap();
+        let mut keys = rows(&storage, "User")
+            .await
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        keys.reverse();
+        let bulk_deletes = discord.calls("delete_messages");
+        storage.delete_data("User", keys).await.unwrap();
+        let recreated = channel(discord.clone()).await;
+        assert_ne!(recreated.id, before.id);
+        assert_eq!(recreated.position, before.position);
+        assert_eq!(discord.calls("delete_messages"), bulk_deletes);
+        assert!(rows(&storage, "User").await.is_empty());
+
+        storage
+            .append_data("User", (38..=49).map(|id| row(id, "sql")).collect())
+            .await
+            .unwrap();
+        storage.truncate_table("User").await.unwrap();
+
+        let after = channel(discord.clone()).await;
+        assert_ne!(after.id, recreated.id);
+        assert_eq!(after.position, before.position);
+        assert_eq!(discord.guild().channels().len(), 1);
+        assert!(rows(&storage, "User").await.is_empty());
+
+        // only the schema is pinned again, the pin of a person is not copied as a bot message
+        let pins = discord.get_pins(after.id).await.unwrap();
+        assert_eq!(pins.len(), 1);
+        assert!(pins[0].author.bot);
+        storage.invalidate_channel_cache();
+        storage.invalidate_schema_cache();
+        assert_eq!(
+            storage
+                .fetch_schema("User")
+                .await
+                .unwrap()
+                .unwrap()
+                .table_name,
+            "User"
+        );
+    }
+
+    #[tokio::test]
+    async fn truncate_keeps_indexed_and_schemaless_channels() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", (1..=12).map(|id| row(id, "glue")).collect())
+            .await
+            .unwrap();
+        let column = OrderByExpr {
+            expr: Expr::Identifier("id".to_owned()),
+            asc: None,
+        };
+        storage
+            .create_index("User", "idx_id", &column)
+            .await
+            .unwrap();
+
+        let channel_id = |discord: FakeDiscord, name: &'static str| async move {
+            discord
+                .get_channel_id(discord.guild_id(), name)
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        let before = channel_id(discord.clone(), "user").await;
+        storage.truncate_table("User").await.unwrap();
+        assert_eq!(channel_id(discord.clone(), "user").await, before);
+        assert!(rows(&storage, "User").await.is_empty());
+        assert!(indexed(&storage, None, None).await.is_empty());
+
+        storage
+            .append_data("User", vec![row(1, "a")])
+            .await
+            .unwrap();
+        assert_eq!(indexed(&storage, None, None).await, vec![row(1, "a")]);
+
+        let mut builder = serenity::builder::CreateChannel::default();
+        builder.name("general");
+        let general = discord
+            .create_channel(discord.guild_id(), builder)
+            .await
+            .unwrap();
+        discord
+            .send_message(general.id, "hello".to_owned())
+            .await
+            .unwrap();
+        storage.truncate_table("general").await.unwrap();
+        assert_eq!(channel_id(discord.clone(), "general").await, general.id);
+        assert!(rows(&storage, "general").await.is_empty());
+        assert!(discord.get_pins(general.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn alter_table_rewrites_rows() {
+        let discord = FakeDiscord::new("test");
+        let mut storage = discord.clone().into_storage();
+        storage.insert_schema(&schema("User")).await.unwrap();
+        storage
+            .append_data("User", vec![row(1, "glue"), row(2, "sql")])
+            .await
+            .unwrap();
+
+        storage.rename_schema("User", "Member").await.unwrap();
+        assert!(storage.fetch_schema("User").await.unwrap().is_none());
+        assert_eq!(rows(&storage, "Member").await.len(), 2);
+
+        let email = ColumnDef {
+            name: "email".to_owned(),
+            data_type: DataType::Text,
+            nullable: true,
+            default: None,
+            unique: None,
+        };
+        storage.add_column("Member", &email).await.unwrap();
+        assert!(matches!(
+            storage.add_column("Member", &email).await,
+            Err(gluesql_core::result::Error::AlterTable(
+                AlterTableError::AlreadyExistingColumn(_)
+            ))
+        ));
+        let required = ColumnDef {
+            name: "age".to_owned(),
+            nullable: false,
+            ..email.clone()
+        };
+        assert!(matches!(
+            storage.add_column("Member", &required).await,
+            Err(gluesql_core::result::Error::AlterTable(
+                AlterTableError::DefaultValueRequired(_)
+            ))
+        ));
+
+        storage
+            .rename_column("Member", "name", "nickname")
+            .await
+            .unwrap();
+        storage.drop_column("Member", "id", false).await.unwrap();
+        storage.drop_column("Member", "id", true).await.unwrap();
+
+        let restarted = discord.into_storage();
+        let fetched = restarted.fetch_schema("Member").await.unwrap().unwrap();
+        assert_eq!(fetched.table_name, "Member");
+        assert_eq!(
+            fetched
+                .column_defs
+                .unwrap()
+                .into_iter()
+                .map(|column_def| column_def.name)
+                .collect::<Vec<_>>(),
+            vec!["nickname", "email"]
+        );
+        assert_eq!(
+            rows(&restarted, "Member")
+                .await
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect::<Vec<_>>(),
+            vec![
+                DataRow::Vec(vec![Value::Str("glue".to_owned()), Value::Null]),
+                DataRow::Vec(vec![Value::Str("sql".to_owned()), Value::Null]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn metadata_columns() {
+        let options = StorageOptions {
+            metadata_columns: true,
+            ..StorageOptions::default()
+        };
+        let discord = FakeDiscord::new("test");
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        storage.insert_schema(&schema("User")).await.unwrap();
+
+        let schema = storage.fetch_schema("User").await.unwrap().unwrap();
+        let columns = schema
+            .column_defs
+            .unwrap()
+            .into_iter()
+            .map(|column_def| column_def.name)
+            .collect::<Vec<_>>();
+        assert_eq!(columns.len(), 9);
+        assert_eq!(columns[2], "_id");
+        assert_eq!(columns[8], "_attachments");
+
+        let mut written = vec![Value::I64(1), Value::Str("glue".to_owned())];
+        written.resize(9, Value::Null);
+        storage
+            .append_data("User", vec![DataRow::Vec(written)])
+            .await
+            .unwrap();
+
+        let (key, values) = match rows(&storage, "User").await.remove(0) {
+            (key, DataRow::Vec(values)) => (key, values),
+            (_, scanned) => panic!("unexpected row: {scanned:?}"),
+        };
+        assert_eq!(values.len(), 9);
+        assert_eq!(
+            Key::Str(match &values[2] {
+                Value::Str(id) => id.clone(),
+                value => panic!("unexpected id: {value:?}"),
+            }),
+            key
+        );
+        assert!(matches!(values[3], Value::Timestamp(_)));
+        assert_eq!(values[4], Value::Null);
+        assert_eq!(values[7], Value::Bool(false));
+        assert_eq!(values[8], Value::List(vec![]));
+        assert_eq!(
+            storage.fetch_data("User", &key).await.unwrap(),
+            Some(DataRow::Vec(values))
+        );
+
+        let plain = discord.clone().into_storage();
+        assert_eq!(rows(&plain, "User").await[0].1, row(1, "glue"));
+
+        let mut builder = serenity::builder::CreateChannel::default();
+        builder.name("hello-world");
+        let channel = discord
+            .create_channel(discord.guild_id(), builder)
+            .await
+            .unwrap();
+        let message = discord
+            .send_message(channel.id, "hello".to_owned())
+            .await
+            .unwrap();
+
+        let chat = match rows(&storage, "hello-world").await.remove(0).1 {
+            DataRow::Map(chat) => chat,
+            scanned => panic!("unexpected row: {scanned:?}"),
+        };
+        assert_eq!(
+            chat.get("_author_name"),
+            Some(&Value::Str(message.author.name))
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_columns() {
+        let options = StorageOptions {
+            chat_columns: true,
+            ..StorageOptions::default()
+        };
+        let discord = FakeDiscord::new("test");
+        let storage = DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+
+        let mut builder = serenity::builder::CreateChannel::default();
+        builder.name("general");
+        let channel = discord
+            .create_channel(discord.guild_id(), builder)
+            .await
+            .unwrap();
+        let question = discord
+            .send_message(channel.id, "anyone?".to_owned())
+            .await
+            .unwrap();
+        let human = FakeGuild::user(UserId(42), "glue", false);
+        discord
+            .guild_mut()
+            .reply_as(
+                channel.id,
+                &human,
+                question.id,
+                "<@123> try https://gluesql.org ```sql\nSELECT 1;\n```",
+            )
+            .unwrap();
+
+        let scanned = rows(&storage, "general").await;
+        let reply = match &scanned[1].1 {
+            DataRow::Map(reply) => reply,
+            row => panic!("unexpected row: {row:?}"),
+        };
+        assert_eq!(
+            reply["mentions"],
+            Value::List(vec![Value::Str("123".to_owned())])
+        );
+        assert_eq!(
+            reply["urls"],
+            Value::List(vec![Value::Str("https://gluesql.org".to_owned())])
+        );
+        assert_eq!(reply["reply_to"], Value::Str(question.id.to_string()));
+        assert!(matches!(&reply["code_blocks"], Value::List(blocks) if blocks.len() == 1));
+
+        let plain = discord.clone().into_storage();
+        match &rows(&plain, "general").await[0].1 {
+            DataRow::Map(row) => assert_eq!(row.len(), 1),
+            row => panic!("unexpected row: {row:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reaction_columns() {
+        let options = StorageOptions {
+            reaction_columns: true,
+            ..StorageOptions::default()
+        };
+        let discord = FakeDiscord::new("test");
+        let mut storage =
+            DiscordStorage::with_options(discord.clone(), discord.guild_id(), options);
+        storage.insert_schema(&schema("Poll")).await.unwrap();
+
+        let schema = storage.fetch_schema("Poll").await.unwrap().unwrap();
+        let columns = schema.column_defs.unwrap();
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns[2].name, "_reactions");
+
+        storage
+            .append_data("Poll", vec![row(1, "yes"), row(2, "no")])
+            .await
+            .unwrap();
+        let key = rows(&storage, "Poll").await.remove(0).0;
+
+        assert_eq!(
+            storage
+                .increment_reaction("Poll", &key, '👍')
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            storage
+                .increment_reaction("Poll", &key, '👍')
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(discord.calls("create_reaction"), 1);
+        let message_id = match &key {
+            Key::Str(id) => MessageId(id.parse().unwrap()),
+            key => panic!("unexpected key: {key:?}"),
+        };
+        let channel_id = discord
+            .get_channel_id(discord.guild_id(), "poll")
+            .await
+            .unwrap()
+            .unwrap();
+        discord
+            .guild_mut()
+            .react(channel_id, message_id, UserId(42), ReactionType::from('👍'))
+            .unwrap();
+
+        let reactions = |values: &[(&str, i64)]| {
+            Value::Map(
+                values
+                    .iter()
+                    .map(|(emoji, count)| ((*emoji).to_owned(), Value::I64(*count)))
+                    .collect(),
+            )
+        };
+        let scanned = rows(&storage, "Poll").await;
+        assert_eq!(
+            scanned[0].1,
+            DataRow::Vec(vec![
+                Value::I64(1),
+                Value::Str("yes".to_owned()),
+                reactions(&[("👍", 3)]),
+            ])
+        );
+        assert_eq!(
+            scanned[1].1,
+            DataRow::Vec(vec![
+                Value::I64(2),
+                Value::Str("no".to_owned()),
+                reactions(&[]),
+            ])
+        );
+        assert_eq!(discord.calls("edit_message"), 0);
+
+        // counts of another storage are read back from the changed `<counters>` message
+        let other = DiscordStorage::with_options(
+            discord.clone(),
+            discord.guild_id(),
+            storage.options.clone(),
+        );
+        assert_eq!(
+            other.increment_reaction("Poll", &key, '👍').await.unwrap(),
+            3
+        );
+        assert_eq!(
+            storage.fetch_data("Poll", &key).await.unwrap(),
+            Some(DataRow::Vec(vec![
+                Value::I64(1),
+                Value::Str("yes".to_owned()),
+                reactions(&[("👍", 4)]),
+            ]))
+        );
+
+        for count in [2, 1, 0, 0] {
+            assert_eq!(
+                storage
+                    .decrement_reaction("Poll", &key, '👍')
+                    .await
+                    .unwrap(),
+                count
+            );
+        }
+        assert_eq!(discord.calls("delete_reaction"), 1);
+        assert_eq!(
+            storage.fetch_data("Poll", &key).await.unwrap(),
+            Some(DataRow::Vec(vec![
+                Value::I64(1),
+                Value::Str("yes".to_owned()),
+                reactions(&[("👍", 1)]),
+            ]))
+        );
+
+        let schema = storage.fetch_schema("Poll").await.unwrap().unwrap();
+        assert_eq!(schema.column_defs.unwrap().len(), 3);
+
+        let packed = Key::Str(format!("{message_id}:0"));
+        let err = storage
+            .increment_reaction("Poll", &packed, '👍')
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, gluesql_core::result::Error::Storage(err) if err.downcast_ref::<StorageError>().is_none())
+        );
+
+        storage
+            .increment_reaction("Poll", &key, '👎')
+            .await
+            .unwrap();
+        storage
+            .delete_data("Poll", vec![key.clone()])
+            .await
+            .unwrap();
+        assert!(storage.counters.get(channel_id, message_id).is_empty());
+
+        let missing = Key::Str("1".to_owned());
+        let err = storage
+            .increment_reaction("Poll", &missing, '👍')
+            .await
+            .unwrap_err();
+        match err {
+            gluesql_core::result::Error::Storage(err) => assert!(matches!(
+                err.downcast_ref::<StorageError>(),
+                Some(StorageError::RowNotFound { key, .. }) if key == &missing
+            )),
+            err => panic!("unexpected error: {err:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_messages_with_markers_are_text() {
+        let discord = FakeDiscord::new("test");
+        let storage = discord.clone().into_storage();
+
+        let mut builder = serenity::builder::CreateChannel::default();
+        builder.name("general");
+        let channel = discord
+            .create_channel(discord.guild_id(), builder)
+            .await
+            .unwrap();
+        let human = FakeGuild::user(UserId(42), "glue", false);
+        let contents = [
+            "<chunks:1,2>".to_owned(),
+            "```enc\nnot really\n```".to_owned(),
+            "<chunk>quoted</chunk>".to_owned(),
+        ];
+        let mut keys = Vec::new();
+        for content in &contents {
+            let message = discord
+                .guild_mut()
+                .send_message_as(channel.id, &human, content.as_str())
+                .unwrap();
+            keys.push(Key::Str(message.id.to_string()));
+        }
+
+        let text = |content: &str| {
+            DataRow::Map([("content".to_owned(), Value::Str(content.to_owned()))].into())
+        };
+        let scanned = rows(&storage, "general").await;
+        assert_eq!(
+            scanned.into_iter().map(|(_, row)| row).collect::<Vec<_>>(),
+            contents
+                .iter()
+                .map(|content| text(content))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            storage.fetch_data("general", &keys[1]).await.unwrap(),
+            Some(text(&contents[1]))
+        );
+    }
+
+    #[tokio::test]
+    async fn schemaless_chat_messages() {
+        let discord = FakeDiscord::new("test");
+        let storage = discord.clone().into_storage();
+
+        let mut builder = serenity::builder::CreateChannel::default();
+        builder.name("hello-world");
+        let channel = discord
+            .create_channel(discord.guild_id(), builder)
+            .await
+            .unwrap();
+        discord
+            .send_message(channel.id, "hello".to_owned())
+            .await
+            .unwrap();
+
+        let schema = storage.fetch_schema("hello-world").await.unwrap().unwrap();
+        assert!(schema.column_defs.is_none());
+
+        let rows = rows(&storage, "hello-world").await;
+        assert_eq!(
+            rows[0].1,
+            DataRow::Map([("content".to_owned(), Value::Str("hello".to_owned()))].into())
+        );
     }
 }
@@ -0,0 +1,68 @@
+use serde::Serialize;
+
+use crate::utils;
+
+use super::EncryptionKey;
+
+/// How `DiscordStorage` lays rows out in messages, see [`super::DiscordStorage::with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct StorageOptions {
+    /// Text format of newly written rows, rows already stored in another format still decode.
+    pub row_codec: RowCodec,
+    /// Rows whose message text is longer than this many characters are uploaded as a
+    /// `row.json` attachment of the row message instead of being split into chunk messages.
+    pub attachment_threshold: Option<usize>,
+    /// Store `BYTEA` values as attachments of the row message instead of JSON byte arrays.
+    pub bytea_attachments: bool,
+    /// Encrypt row text and `BYTEA` attachments of newly written rows.
+    /// Plaintext rows still decode, encrypted ones need the same key to be read.
+    pub encryption_key: Option<EncryptionKey>,
+    /// Encrypt the pinned schema message too, tables then can only be opened with the key.
+    pub encrypt_schemas: bool,
+    /// Pack rows appended to tables without a primary key or indexes into shared messages,
+    /// as many as fit in one message. Their keys are `{message_id}:{slot}`.
+    pub pack_rows: bool,
+    /// Upload rows appended to tables without a primary key or indexes as `segment.json`
+    /// attachments of this many rows, with per column min/max statistics in the message.
+    /// Rows are buffered until they fill a segment or `DiscordStorage::flush` is called.
+    /// Takes precedence over `pack_rows`, rows use the same `{message_id}:{slot}` keys.
+    pub segment_rows: Option<usize>,
+    /// Message sends and deletes awaited at once per table, `0` and `1` write one message at a time.
+    /// Sends are also held to the requests left in serenity's rate limit bucket of the channel,
+    /// and rows appended to tables without a primary key are always sent in order.
+    pub write_concurrency: usize,
+    /// Add the `_id`, `_created_at`, `_edited_at`, `_author_id`, `_author_name`, `_pinned` and
+    /// `_attachments` columns of the row message to every table, after the declared columns.
+    /// They are read only, values written to them are not stored.
+    pub metadata_columns: bool,
+    /// Add `mentions`, `role_mentions`, `channels`, `urls`, `code_blocks`, `emoji` and `reply_to`
+    /// columns parsed from the message to rows of messages that hold no stored row, which
+    /// otherwise only have a `content` column.
+    pub chat_columns: bool,
+    /// Add a `_reactions` column to every table, after the declared and metadata columns,
+    /// mapping each emoji reacted to the row message to its count, the reactions of users plus
+    /// the counter of `DiscordStorage::increment_reaction`. Custom emoji are keyed `name:id`.
+    pub reaction_columns: bool,
+}
+
+/// Row message text formats, told apart by their code fence when reading.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RowCodec {
+    /// ` ```json ` fenced, pretty printed JSON, readable in the Discord client
+    #[default]
+    Pretty,
+    /// ` ```json ` fenced JSON without whitespace
+    Minified,
+    /// ` ```deflate ` fenced base64 of the deflated JSON
+    Compressed,
+}
+
+impl RowCodec {
+    pub fn encode<T: Serialize>(&self, data: &T) -> eyre::Result<String> {
+        match self {
+            Self::Pretty => utils::to_discord_json(data),
+            Self::Minified => utils::to_minified_json(data),
+            Self::Compressed => utils::to_compressed_json(data),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Packed row messages, holding several rows of a table without a primary key in slots.
+//! A row keeps its slot until it is deleted, deleted rows leave an empty slot behind.
+
+use gluesql_core::prelude::Key;
+use serenity::model::prelude::MessageId;
+
+const HEAD: &str = "<packed>";
+
+/// message text holding `text`, the encoded slots
+pub fn content(text: &str) -> String {
+    format!("{HEAD}\n{text}")
+}
+
+/// encoded slots of a packed message text, `None` for any other text
+pub fn parse(text: &str) -> Option<&str> {
+    text.strip_prefix(HEAD).map(str::trim_start)
+}
+
+/// key of the row in `slot` of the packed message `message_id`
+pub fn key(message_id: MessageId, slot: usize) -> Key {
+    Key::Str(format!("{message_id}:{slot}"))
+}
+
+/// message id and packed slot of a row key of a table without a primary key
+pub fn parse_key(key: &str) -> Option<(MessageId, Option<usize>)> {
+    match key.split_once(':') {
+        Some((message_id, slot)) => Some((
+            MessageId(message_id.parse().ok()?),
+            Some(slot.parse().ok()?),
+        )),
+        None => Some((MessageId(key.parse().ok()?), None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql_core::prelude::Key;
+    use serenity::model::prelude::MessageId;
+
+    use super::{content, key, parse, parse_key};
+
+    #[test]
+    fn key_roundtrip() {
+        let key = match key(MessageId(42), 3) {
+            Key::Str(key) => key,
+            key => panic!("unexpected key: {key:?}"),
+        };
+
+        assert_eq!(parse_key(&key), Some((MessageId(42), Some(3))));
+        assert_eq!(parse_key("42"), Some((MessageId(42), None)));
+        assert_eq!(parse_key("42:"), None);
+        assert_eq!(parse_key("pending-0"), None);
+    }
+
+    #[test]
+    fn content_roundtrip() {
+        assert_eq!(parse(&content("[null]")), Some("[null]"));
+        assert_eq!(parse("```json\n[1]\n```"), None);
+    }
+}
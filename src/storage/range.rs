@@ -0,0 +1,125 @@
+//! Slices of a table without a primary key by row key. Row keys are message ids, which are
+//! snowflakes starting with their creation time, so keys and send times order rows the same way.
+
+use gluesql_core::{
+    chrono::{DateTime, Utc},
+    prelude::Key,
+};
+use serenity::model::prelude::MessageId;
+
+use super::packed;
+use crate::transport::DISCORD_EPOCH;
+
+/// Rows from `start` inclusive to `end` exclusive by message id and packed slot,
+/// `None` leaves that side open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyRange {
+    start: Option<(MessageId, usize)>,
+    end: Option<(MessageId, usize)>,
+}
+
+impl KeyRange {
+    /// `Err` with the key that is not a row key of a table without a primary key
+    pub fn new(start: Option<&Key>, end: Option<&Key>) -> Result<Self, Key> {
+        let position = |key: Option<&Key>| match key {
+            None => Ok(None),
+            Some(Key::Str(id)) => match packed::parse_key(id) {
+                Some((message_id, slot)) => Ok(Some((message_id, slot.unwrap_or_default()))),
+                None => Err(Key::Str(id.clone())),
+            },
+            Some(key) => Err(key.clone()),
+        };
+
+        Ok(Self {
+            start: position(start)?,
+            end: position(end)?,
+        })
+    }
+
+    /// rows of the messages sent from `start` inclusive to `end` exclusive
+    pub fn between(start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Self {
+        let position = |time: DateTime<Utc>| (snowflake(time), 0);
+
+        Self {
+            start: start.map(position),
+            end: end.map(position),
+        }
+    }
+
+    /// history is read from the message after this one
+    pub fn after(&self) -> MessageId {
+        let start = self.start.map_or(0, |(message_id, _)| message_id.0);
+
+        MessageId(start.saturating_sub(1))
+    }
+
+    /// whether `message_id` and every message after it hold no row of the range
+    pub fn ends_before(&self, message_id: MessageId) -> bool {
+        matches!(self.end, Some(end) if (message_id, 0) >= end)
+    }
+
+    pub fn contains(&self, message_id: MessageId, slot: Option<usize>) -> bool {
+        let position = (message_id, slot.unwrap_or_default());
+
+        !matches!(self.start, Some(start) if position < start)
+            && !matches!(self.end, Some(end) if position >= end)
+    }
+}
+
+/// lowest message id Discord could hand out at `time`, the inverse of
+/// `MessageId::created_at`
+fn snowflake(time: DateTime<Utc>) -> MessageId {
+    let elapsed = (time.timestamp_millis().max(0) as u64).saturating_sub(DISCORD_EPOCH);
+
+    MessageId(elapsed << 22)
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql_core::{
+        chrono::{TimeZone, Utc},
+        prelude::Key,
+    };
+    use serenity::model::prelude::MessageId;
+
+    use super::KeyRange;
+
+    #[test]
+    fn keys_bound_rows_and_slots() {
+        let range = KeyRange::new(
+            Some(&Key::Str("10".to_owned())),
+            Some(&Key::Str("20:2".to_owned())),
+        )
+        .unwrap();
+
+        assert_eq!(range.after(), MessageId(9));
+        assert!(!range.contains(MessageId(9), None));
+        assert!(range.contains(MessageId(10), None));
+        assert!(range.contains(MessageId(20), Some(1)));
+        assert!(!range.contains(MessageId(20), Some(2)));
+        assert!(!range.ends_before(MessageId(20)));
+        assert!(range.ends_before(MessageId(21)));
+
+        let open = KeyRange::default();
+        assert_eq!(open.after(), MessageId(0));
+        assert!(open.contains(MessageId(1), Some(3)));
+        assert!(!open.ends_before(MessageId(u64::MAX)));
+
+        assert_eq!(KeyRange::new(Some(&Key::I64(1)), None), Err(Key::I64(1)));
+    }
+
+    #[test]
+    fn times_bound_message_ids() {
+        let sent = MessageId(175928847299117063);
+        let created = sent.created_at().unix_timestamp();
+
+        let range = KeyRange::between(
+            Some(Utc.timestamp_opt(created, 0).unwrap()),
+            Some(Utc.timestamp_opt(created + 1, 0).unwrap()),
+        );
+        assert!(range.contains(sent, None));
+
+        let later = KeyRange::between(Some(Utc.timestamp_opt(created + 1, 0).unwrap()), None);
+        assert!(!later.contains(sent, None));
+    }
+}
@@ -0,0 +1,28 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use serenity::model::prelude::ChannelId;
+
+/// Rows counted by the last full scan of a channel, per channel.
+///
+/// Any write to a channel forgets its count, so a count is only held from a full scan to the
+/// next write. gluesql runs a `DELETE` by scanning the table first, so `delete_data` compares
+/// the rows it removes with the count to tell whether every row of the table goes.
+#[derive(Default)]
+pub(crate) struct RowCounts {
+    channels: RefCell<HashMap<ChannelId, usize>>,
+}
+
+impl RowCounts {
+    /// `None` when the channel was written since its last full scan
+    pub fn get(&self, channel_id: ChannelId) -> Option<usize> {
+        self.channels.borrow().get(&channel_id).copied()
+    }
+
+    pub fn insert(&self, channel_id: ChannelId, rows: usize) {
+        self.channels.borrow_mut().insert(channel_id, rows);
+    }
+
+    pub fn remove(&self, channel_id: ChannelId) {
+        self.channels.borrow_mut().remove(&channel_id);
+    }
+}
@@ -0,0 +1,168 @@
+//! Oldest first reading of the rows of a channel, one history page at a time.
+//!
+//! A [`RowReader`] owns its position in the history and borrows the storage only while it
+//! reads, so [`PagedRows`] can hand rows to gluesql from an iterator that outlives the
+//! `scan_data` call, requesting each page as the iterator gets there.
+
+use std::collections::{HashMap, VecDeque};
+
+use gluesql_core::{prelude::Key, store::DataRow};
+use serenity::model::prelude::{ChannelId, Message, MessageId, MessageType};
+use tokio::{runtime::Handle, task};
+
+use super::{
+    chunk, gluesql, is_row_message, range::KeyRange, row_key, scan::RowScan, ColumnFilter,
+    DiscordStorage, IntoStorageErr,
+};
+use crate::transport::Transport;
+
+/// Rows of a channel in `range` that satisfy `filter`, read as the history arrives.
+pub(crate) struct RowReader<'a> {
+    channel_id: ChannelId,
+    range: KeyRange,
+    filter: Option<ColumnFilter<'a>>,
+    /// the next page starts after this message, `None` once the last page was read
+    after: Option<MessageId>,
+    messages: VecDeque<Message>,
+    scan: RowScan<Message>,
+    rows: VecDeque<(Message, Option<usize>, DataRow)>,
+    heads: HashMap<MessageId, Vec<MessageId>>,
+    count: usize,
+    finished: bool,
+}
+
+impl<'a> RowReader<'a> {
+    pub fn new(channel_id: ChannelId, range: KeyRange, filter: Option<ColumnFilter<'a>>) -> Self {
+        Self {
+            channel_id,
+            range,
+            filter,
+            after: Some(range.after()),
+            messages: VecDeque::new(),
+            scan: RowScan::default(),
+            rows: VecDeque::new(),
+            heads: HashMap::new(),
+            count: 0,
+            finished: false,
+        }
+    }
+
+    /// Next row with the message holding it, the next page is requested only once the
+    /// previous one is read. Reading the whole history unfiltered rebuilds the channel's
+    /// head map and counts its rows.
+    pub async fn next<T: Transport>(
+        &mut self,
+        storage: &DiscordStorage<T>,
+    ) -> eyre::Result<Option<(Message, Option<usize>, DataRow)>> {
+        loop {
+            if let Some(row) = self.rows.pop_front() {
+                self.count += 1;
+                return Ok(Some(row));
+            }
+
+            if let Some((message, text)) = self.scan.next_head() {
+                if let Some(chunk_ids) = chunk::parse_head(&message.content) {
+                    self.heads.insert(message.id, chunk_ids);
+                }
+                if let Some(filter) = self.filter.filter(|_| is_row_message(&message)) {
+                    match storage.decode_segment_stats(message.id, &text)? {
+                        Some(stats) if filter.excludes(&stats) => continue,
+                        _ => {}
+                    }
+                }
+                let decoded = storage.decode_message(message, text).await?;
+
+                let (range, filter) = (self.range, self.filter);
+                self.rows
+                    .extend(decoded.into_iter().filter(|(message, slot, row)| {
+                        range.contains(message.id, *slot)
+                            && filter.iter().all(|filter| filter.matches(row))
+                    }));
+                continue;
+            }
+
+            if self.scan.is_finished() {
+                if !self.finished && self.range == KeyRange::default() && self.filter.is_none() {
+                    storage
+                        .heads
+                        .replace(self.channel_id, std::mem::take(&mut self.heads));
+                    storage.row_counts.insert(self.channel_id, self.count);
+                }
+                self.finished = true;
+
+                return Ok(None);
+            }
+
+            let message = match self.messages.pop_front() {
+                Some(message) => message,
+                None => {
+                    match self.after {
+                        Some(after) => {
+                            let (page, next) = storage
+                                .discord
+                                .messages_after(self.channel_id, after)
+                                .await?;
+                            self.after = next;
+                            self.messages.extend(page);
+                        }
+                        None => self.scan.finish(),
+                    }
+                    continue;
+                }
+            };
+
+            if self.range.ends_before(message.id) {
+                self.scan.finish();
+            } else if matches!(message.kind, MessageType::Regular) && !message.pinned {
+                let content = storage.content(&message);
+                if is_row_message(&message) {
+                    self.scan.push(message.id, message, content);
+                } else {
+                    self.scan.push_text(message, content);
+                }
+            }
+        }
+    }
+}
+
+/// The `RowIter` of `scan_data`, blocking the thread on each page request, which only a
+/// multi-thread runtime allows. `storage` shares the transport and scan caches of the
+/// storage that was scanned.
+pub(crate) struct PagedRows<T: Transport> {
+    storage: DiscordStorage<T>,
+    handle: Handle,
+    reader: RowReader<'static>,
+}
+
+impl<T: Transport> PagedRows<T> {
+    pub fn new(storage: DiscordStorage<T>, handle: Handle, channel_id: ChannelId) -> Self {
+        Self {
+            storage,
+            handle,
+            reader: RowReader::new(channel_id, KeyRange::default(), None),
+        }
+    }
+}
+
+impl<T: Transport> Iterator for PagedRows<T> {
+    type Item = gluesql::Result<(Key, DataRow)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self {
+            storage,
+            handle,
+            reader,
+        } = self;
+        let row = task::block_in_place(|| handle.block_on(reader.next(storage)));
+
+        match row.into_storage_err() {
+            Ok(row) => row.map(|(message, slot, row)| {
+                Ok((
+                    row_key(message.id, slot),
+                    storage.with_metadata(&message, row),
+                ))
+            }),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Oldest first reading of row messages, joining chunked rows back as their continuation
+//! messages arrive. An edited row may list continuation messages newer than its head,
+//! so heads wait in history order until every continuation they list has been read.
+//! A scan of a slice of the history may end before them, the caller fetches those on its own.
+//! Messages written by people are never read as chunks, whatever their content.
+
+use std::collections::{HashMap, VecDeque};
+
+use serenity::model::prelude::MessageId;
+
+use super::chunk;
+
+/// Heads and continuation texts read so far, `T` is the message handed back with its head text.
+pub struct RowScan<T> {
+    chunks: HashMap<MessageId, String>,
+    /// with the continuation messages each head lists
+    heads: VecDeque<(T, String, Option<Vec<MessageId>>)>,
+    exhausted: bool,
+}
+
+impl<T> Default for RowScan<T> {
+    fn default() -> Self {
+        Self {
+            chunks: HashMap::new(),
+            heads: VecDeque::new(),
+            exhausted: false,
+        }
+    }
+}
+
+impl<T> RowScan<T> {
+    /// takes the next message of the history with its content
+    pub fn push(&mut self, message_id: MessageId, message: T, content: String) {
+        match chunk::parse_chunk(&content) {
+            Some(text) => {
+                self.chunks.insert(message_id, text.to_owned());
+            }
+            None => {
+                let chunk_ids = chunk::parse_head(&content);
+                self.heads.push_back((message, content, chunk_ids));
+            }
+        }
+    }
+
+    /// takes the next message of the history as plain text, it neither is nor lists a chunk
+    pub fn push_text(&mut self, message: T, content: String) {
+        self.heads.push_back((message, content, None));
+    }
+
+    /// marks the end of the history or of the scanned slice of it
+    pub fn finish(&mut self) {
+        self.exhausted = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.exhausted && self.heads.is_empty()
+    }
+
+    /// Next head in history order with its full text, `None` until its continuations were read.
+    /// Once finished, heads whose continuations were not read come back with the head content.
+    pub fn next_head(&mut self) -> Option<(T, String)> {
+        let chunk_ids = match self.heads.front()? {
+            (_, _, Some(chunk_ids)) => chunk_ids,
+            (_, _, None) => return self.pop_head(),
+        };
+
+        let arrived = chunk_ids
+            .iter()
+            .all(|chunk_id| self.chunks.contains_key(chunk_id));
+        if !arrived && !self.exhausted {
+            return None;
+        }
+
+        let (message, content, chunk_ids) = self.heads.pop_front()?;
+        let chunk_ids = chunk_ids.unwrap_or_default();
+        if !arrived {
+            return Some((message, content));
+        }
+
+        let text = chunk_ids
+            .iter()
+            .filter_map(|chunk_id| self.chunks.remove(chunk_id))
+            .collect();
+
+        Some((message, text))
+    }
+
+    fn pop_head(&mut self) -> Option<(T, String)> {
+        self.heads
+            .pop_front()
+            .map(|(message, content, _)| (message, content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::prelude::MessageId;
+
+    use super::RowScan;
+    use crate::storage::chunk;
+
+    #[test]
+    fn heads_wait_for_newer_chunks() {
+        let mut scan = RowScan::default();
+        scan.push(MessageId(1), "a", "first".to_owned());
+        scan.push(
+            MessageId(2),
+            "b",
+            chunk::head(&[MessageId(4), MessageId(3)]),
+        );
+        scan.push(MessageId(3), "", "<chunk>lo</chunk>".to_owned());
+        scan.push(MessageId(5), "c", "third".to_owned());
+
+        assert_eq!(scan.next_head().unwrap(), ("a", "first".to_owned()));
+        assert!(scan.next_head().is_none());
+
+        scan.push(MessageId(4), "", "<chunk>hel</chunk>".to_owned());
+        assert_eq!(scan.next_head().unwrap(), ("b", "hello".to_owned()));
+        assert_eq!(scan.next_head().unwrap(), ("c", "third".to_owned()));
+        assert!(scan.next_head().is_none());
+
+        scan.finish();
+        assert!(scan.is_finished());
+    }
+
+    #[test]
+    fn unread_chunks_leave_the_head_once_finished() {
+        let mut scan = RowScan::default();
+        let head = chunk::head(&[MessageId(2)]);
+        scan.push(MessageId(1), (), head.clone());
+        assert!(scan.next_head().is_none());
+
+        scan.finish();
+        assert_eq!(scan.next_head(), Some(((), head)));
+        assert!(scan.is_finished());
+    }
+
+    #[test]
+    fn text_is_neither_chunk_nor_head() {
+        let mut scan = RowScan::default();
+        scan.push_text("a", "<chunk>hi</chunk>".to_owned());
+        scan.push_text("b", chunk::head(&[MessageId(3)]));
+
+        assert_eq!(
+            scan.next_head().unwrap(),
+            ("a", "<chunk>hi</chunk>".to_owned())
+        );
+        assert_eq!(
+            scan.next_head().unwrap(),
+            ("b", chunk::head(&[MessageId(3)]))
+        );
+        assert!(scan.next_head().is_none());
+    }
+}
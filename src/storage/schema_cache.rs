@@ -0,0 +1,65 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use gluesql_core::data::Schema;
+use serenity::model::{
+    prelude::{ChannelId, GuildChannel},
+    Timestamp,
+};
+
+struct CachedSchema {
+    last_pin_timestamp: Option<Timestamp>,
+    schema: Schema,
+}
+
+/// Parsed pinned schemas per channel.
+///
+/// An entry is only used while the channel's `last_pin_timestamp` matches the one
+/// it was cached with, so re-pinning a schema message elsewhere refreshes it.
+#[derive(Default)]
+pub(crate) struct SchemaCache {
+    schemas: RefCell<HashMap<ChannelId, CachedSchema>>,
+    stale: Arc<AtomicBool>,
+}
+
+impl SchemaCache {
+    pub fn get(&self, channel: &GuildChannel) -> Option<Schema> {
+        if self.stale.swap(false, Ordering::AcqRel) {
+            self.schemas.borrow_mut().clear();
+            return None;
+        }
+
+        let schemas = self.schemas.borrow();
+        let cached = schemas.get(&channel.id)?;
+
+        (cached.last_pin_timestamp == channel.last_pin_timestamp).then(|| cached.schema.clone())
+    }
+
+    pub fn insert(&self, channel: &GuildChannel, schema: Schema) {
+        self.schemas.borrow_mut().insert(
+            channel.id,
+            CachedSchema {
+                last_pin_timestamp: channel.last_pin_timestamp,
+                schema,
+            },
+        );
+    }
+
+    pub fn remove(&self, channel_id: ChannelId) {
+        self.schemas.borrow_mut().remove(&channel_id);
+    }
+
+    pub fn invalidate(&self) {
+        self.schemas.borrow_mut().clear();
+    }
+
+    pub fn stale_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stale)
+    }
+}
@@ -0,0 +1,243 @@
+//! Segment messages of bulk loaded tables without a primary key.
+//!
+//! The rows are uploaded as the `segment.json` attachment, slotted like packed messages,
+//! and the message content holds `<segment>` followed by the [`SegmentStats`] of the rows.
+//! Appended rows wait in a [`SegmentBuffer`] until they fill a segment or are flushed.
+
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap};
+
+use gluesql_core::{ast::IndexOperator, prelude::Value, store::DataRow};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::ChannelId;
+
+pub const FILENAME: &str = "segment.json";
+
+const HEAD: &str = "<segment>";
+
+/// message content holding `text`, the encoded statistics
+pub fn content(text: &str) -> String {
+    format!("{HEAD}\n{text}")
+}
+
+/// encoded statistics of a segment message text, `None` for any other text
+pub fn parse(text: &str) -> Option<&str> {
+    text.strip_prefix(HEAD).map(str::trim_start)
+}
+
+/// Row count and per column value range of a segment, columns by position in the row.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentStats {
+    /// rows left in the segment, deleted rows are not counted
+    pub rows: usize,
+    /// `None` when the column holds only `NULL`s or values that do not compare
+    pub columns: Vec<Option<ColumnStats>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub min: Value,
+    pub max: Value,
+}
+
+impl SegmentStats {
+    pub fn new(slots: &[Option<DataRow>]) -> Self {
+        let rows = slots.iter().flatten().collect::<Vec<_>>();
+
+        let width = rows
+            .iter()
+            .map(|row| match row {
+                DataRow::Vec(values) => values.len(),
+                DataRow::Map(_) => 0,
+            })
+            .max()
+            .unwrap_or_default();
+        let columns = (0..width)
+            .map(|column| {
+                let values = rows.iter().filter_map(|row| match row {
+                    DataRow::Vec(values) => values.get(column),
+                    DataRow::Map(_) => None,
+                });
+
+                ColumnStats::new(values)
+            })
+            .collect();
+
+        Self {
+            rows: rows.len(),
+            columns,
+        }
+    }
+
+    /// Whether no row of the segment can satisfy `column {operator} value`,
+    /// so a filtered scan may skip the segment without downloading it.
+    pub fn excludes(&self, column: usize, operator: &IndexOperator, value: &Value) -> bool {
+        if self.rows == 0 {
+            return true;
+        }
+
+        let ColumnStats { min, max } = match self.columns.get(column) {
+            Some(Some(stats)) => stats,
+            _ => return false,
+        };
+        let (min, max) = match (min.partial_cmp(value), max.partial_cmp(value)) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return false,
+        };
+
+        match operator {
+            IndexOperator::Gt => max != Ordering::Greater,
+            IndexOperator::GtEq => max == Ordering::Less,
+            IndexOperator::Lt => min != Ordering::Less,
+            IndexOperator::LtEq => min == Ordering::Greater,
+            IndexOperator::Eq => min == Ordering::Greater || max == Ordering::Less,
+        }
+    }
+}
+
+/// Rows appended in segment mode that do not fill a segment yet, per channel.
+#[derive(Default)]
+pub(crate) struct SegmentBuffer {
+    channels: RefCell<HashMap<ChannelId, Vec<DataRow>>>,
+}
+
+impl SegmentBuffer {
+    /// buffers `rows` after the rows already waiting and takes those of every segment
+    /// of `segment_rows` rows they fill, oldest first
+    pub fn push(
+        &self,
+        channel_id: ChannelId,
+        rows: Vec<DataRow>,
+        segment_rows: usize,
+    ) -> Vec<DataRow> {
+        let mut channels = self.channels.borrow_mut();
+        let buffered = channels.entry(channel_id).or_default();
+        buffered.extend(rows);
+
+        let filled = buffered.len() - buffered.len() % segment_rows.max(1);
+        let rows = buffered.drain(..filled).collect();
+        if buffered.is_empty() {
+            channels.remove(&channel_id);
+        }
+
+        rows
+    }
+
+    /// takes every row waiting for the channel
+    pub fn take(&self, channel_id: ChannelId) -> Vec<DataRow> {
+        self.channels
+            .borrow_mut()
+            .remove(&channel_id)
+            .unwrap_or_default()
+    }
+
+    pub fn channel_ids(&self) -> Vec<ChannelId> {
+        self.channels.borrow().keys().copied().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.borrow().is_empty()
+    }
+}
+
+impl ColumnStats {
+    fn new<'a>(values: impl Iterator<Item = &'a Value>) -> Option<Self> {
+        let mut stats: Option<Self> = None;
+        for value in values.filter(|value| !matches!(value, Value::Null)) {
+            stats = match stats {
+                None => Some(Self {
+                    min: value.clone(),
+                    max: value.clone(),
+                }),
+                Some(Self { min, max }) => {
+                    let (to_min, to_max) = match (value.partial_cmp(&min), value.partial_cmp(&max))
+                    {
+                        (Some(to_min), Some(to_max)) => (to_min, to_max),
+                        _ => return None,
+                    };
+
+                    Some(Self {
+                        min: if to_min == Ordering::Less {
+                            value.clone()
+                        } else {
+                            min
+                        },
+                        max: if to_max == Ordering::Greater {
+                            value.clone()
+                        } else {
+                            max
+                        },
+                    })
+                }
+            };
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql_core::{ast::IndexOperator, prelude::Value, store::DataRow};
+
+    use serenity::model::prelude::ChannelId;
+
+    use super::{content, parse, ColumnStats, SegmentBuffer, SegmentStats};
+
+    fn row(id: i64, name: Option<&str>) -> Option<DataRow> {
+        let name = name.map_or(Value::Null, |name| Value::Str(name.to_owned()));
+
+        Some(DataRow::Vec(vec![Value::I64(id), name]))
+    }
+
+    #[test]
+    fn stats_by_column() {
+        let stats = SegmentStats::new(&[row(3, None), None, row(1, None), row(7, None)]);
+
+        assert_eq!(stats.rows, 3);
+        assert_eq!(
+            stats.columns,
+            vec![
+                Some(ColumnStats {
+                    min: Value::I64(1),
+                    max: Value::I64(7),
+                }),
+                None,
+            ]
+        );
+
+        assert!(stats.excludes(0, &IndexOperator::Gt, &Value::I64(7)));
+        assert!(!stats.excludes(0, &IndexOperator::GtEq, &Value::I64(7)));
+        assert!(stats.excludes(0, &IndexOperator::Lt, &Value::I64(1)));
+        assert!(stats.excludes(0, &IndexOperator::Eq, &Value::I64(8)));
+        assert!(!stats.excludes(0, &IndexOperator::Eq, &Value::I64(2)));
+        assert!(!stats.excludes(1, &IndexOperator::Eq, &Value::Str("a".to_owned())));
+
+        let mixed = SegmentStats::new(&[
+            row(1, Some("a")),
+            Some(DataRow::Vec(vec![Value::Bool(true)])),
+        ]);
+        assert_eq!(mixed.columns[0], None);
+    }
+
+    #[test]
+    fn buffer_takes_filled_segments() {
+        let buffer = SegmentBuffer::default();
+        let rows = |ids: std::ops::RangeInclusive<i64>| {
+            ids.map(|id| row(id, None).unwrap()).collect::<Vec<_>>()
+        };
+
+        assert!(buffer.push(ChannelId(1), rows(1..=3), 4).is_empty());
+        assert_eq!(buffer.push(ChannelId(1), rows(4..=9), 4), rows(1..=8));
+        assert_eq!(buffer.push(ChannelId(2), rows(1..=4), 4), rows(1..=4));
+        assert_eq!(buffer.channel_ids(), vec![ChannelId(1)]);
+
+        assert_eq!(buffer.take(ChannelId(1)), rows(9..=9));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn content_roundtrip() {
+        assert_eq!(parse(&content("{}")), Some("{}"));
+        assert_eq!(parse("<packed>\n[]"), None);
+    }
+}
@@ -0,0 +1,188 @@
+use std::cmp::Ordering;
+
+use gluesql_core::{prelude::Key, store::DataRow};
+
+/// Writes buffered between `BEGIN` and `COMMIT`, per lowercased table name
+/// in the order the tables were first written.
+#[derive(Debug, Default)]
+pub struct WriteSet {
+    tables: Vec<(String, TableWrites)>,
+    appended: usize,
+}
+
+/// Net effect of the buffered writes on one table.
+#[derive(Debug, Default)]
+pub struct TableWrites {
+    /// rows stored under an existing key, `None` deletes the row
+    pub changes: Vec<(Key, Option<DataRow>)>,
+    /// rows of tables without a primary key, under provisional keys until they are sent
+    pub appended: Vec<(Key, DataRow)>,
+}
+
+impl WriteSet {
+    pub fn table(&self, table_name: &str) -> Option<&TableWrites> {
+        self.tables
+            .iter()
+            .find(|(name, _)| name == table_name)
+            .map(|(_, writes)| writes)
+    }
+
+    pub fn table_mut(&mut self, table_name: &str) -> &mut TableWrites {
+        let position = match self.tables.iter().position(|(name, _)| name == table_name) {
+            Some(position) => position,
+            None => {
+                self.tables
+                    .push((table_name.to_owned(), TableWrites::default()));
+                self.tables.len() - 1
+            }
+        };
+
+        &mut self.tables[position].1
+    }
+
+    /// provisional keys never parse as a message id, so they cannot clash with stored rows
+    pub fn append(&mut self, table_name: &str, row: DataRow) {
+        let key = Key::Str(format!("pending-{}", self.appended));
+        self.appended += 1;
+
+        self.table_mut(table_name).appended.push((key, row));
+    }
+
+    pub fn remove(&mut self, table_name: &str) {
+        self.tables.retain(|(name, _)| name != table_name);
+    }
+
+    pub fn into_tables(self) -> Vec<(String, TableWrites)> {
+        self.tables
+    }
+}
+
+impl TableWrites {
+    pub fn upsert(&mut self, key: Key, row: DataRow) {
+        match self
+            .appended
+            .iter_mut()
+            .find(|(appended, _)| appended == &key)
+        {
+            Some((_, appended)) => *appended = row,
+            None => self.set(key, Some(row)),
+        }
+    }
+
+    pub fn delete(&mut self, key: Key) {
+        let len = self.appended.len();
+        self.appended.retain(|(appended, _)| appended != &key);
+
+        if self.appended.len() == len {
+            self.set(key, None);
+        }
+    }
+
+    fn set(&mut self, key: Key, row: Option<DataRow>) {
+        match self.changes.iter_mut().find(|(changed, _)| changed == &key) {
+            Some((_, changed)) => *changed = row,
+            None => self.changes.push((key, row)),
+        }
+    }
+
+    /// `None` when the transaction did not touch `key`, `Some(None)` when it deleted the row
+    pub fn get(&self, key: &Key) -> Option<Option<&DataRow>> {
+        if let Some((_, row)) = self.appended.iter().find(|(appended, _)| appended == key) {
+            return Some(Some(row));
+        }
+
+        self.changes
+            .iter()
+            .find(|(changed, _)| changed == key)
+            .map(|(_, row)| row.as_ref())
+    }
+
+    /// `rows` as the transaction sees them. Primary key tables gain their new keys and stay
+    /// sorted, other tables only see updates of rows that exist and their appended rows last.
+    pub fn apply(&self, rows: Vec<(Key, DataRow)>, primary_key: bool) -> Vec<(Key, DataRow)> {
+        let mut rows = rows
+            .into_iter()
+            .filter_map(|(key, row)| match self.get(&key) {
+                Some(changed) => changed.map(|row| (key, row.clone())),
+                None => Some((key, row)),
+            })
+            .collect::<Vec<_>>();
+
+        if primary_key {
+            let inserted = self
+                .changes
+                .iter()
+                .filter(|(key, _)| !rows.iter().any(|(existing, _)| existing == key))
+                .filter_map(|(key, row)| row.clone().map(|row| (key.clone(), row)))
+                .collect::<Vec<_>>();
+
+            rows.extend(inserted);
+            rows.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        }
+
+        rows.extend(self.appended.iter().cloned());
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql_core::{
+        prelude::{Key, Value},
+        store::DataRow,
+    };
+
+    use super::WriteSet;
+
+    fn row(id: i64) -> DataRow {
+        DataRow::Vec(vec![Value::I64(id)])
+    }
+
+    #[test]
+    fn writes_are_folded_per_key() {
+        let mut write_set = WriteSet::default();
+        write_set.append("user", row(3));
+        write_set.append("user", row(4));
+
+        let writes = write_set.table_mut("user");
+        writes.upsert(Key::Str("pending-0".to_owned()), row(30));
+        writes.delete(Key::Str("pending-1".to_owned()));
+        writes.upsert(Key::Str("1".to_owned()), row(10));
+        writes.delete(Key::Str("2".to_owned()));
+        writes.upsert(Key::Str("9".to_owned()), row(9));
+
+        let stored = vec![
+            (Key::Str("1".to_owned()), row(1)),
+            (Key::Str("2".to_owned()), row(2)),
+        ];
+        assert_eq!(
+            write_set.table("user").unwrap().apply(stored, false),
+            vec![
+                (Key::Str("1".to_owned()), row(10)),
+                (Key::Str("pending-0".to_owned()), row(30)),
+            ]
+        );
+        assert_eq!(
+            write_set
+                .table("user")
+                .unwrap()
+                .get(&Key::Str("2".to_owned())),
+            Some(None)
+        );
+        assert_eq!(write_set.table("other").map(|_| ()), None);
+    }
+
+    #[test]
+    fn primary_key_rows_stay_sorted() {
+        let mut write_set = WriteSet::default();
+        let writes = write_set.table_mut("user");
+        writes.upsert(Key::I64(2), row(2));
+        writes.delete(Key::I64(3));
+
+        let stored = vec![(Key::I64(1), row(1)), (Key::I64(3), row(3))];
+        assert_eq!(
+            writes.apply(stored, true),
+            vec![(Key::I64(1), row(1)), (Key::I64(2), row(2))]
+        );
+    }
+}
@@ -0,0 +1,1192 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Bound,
+    rc::Rc,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use eyre::Context;
+use gluesql_core::chrono::{Duration, Utc};
+use serde_json::{json, Value as Json};
+use serenity::{
+    builder::CreateChannel,
+    cache::Cache,
+    futures::{
+        stream::{self, LocalBoxStream},
+        StreamExt, TryStreamExt,
+    },
+    model::{
+        prelude::{
+            Attachment, AttachmentId, Channel, ChannelId, GuildChannel, GuildId, Message,
+            MessageId, MessageReaction, ReactionType, UserId,
+        },
+        user::User,
+        Timestamp,
+    },
+};
+
+use crate::{
+    storage::DiscordStorage,
+    transport::{MessageFile, Transport, DISCORD_EPOCH},
+};
+
+/// Discord rejects message content longer than this (in characters)
+pub const MESSAGE_CONTENT_LIMIT: usize = 2000;
+
+/// Discord returns at most this many messages per history request
+pub const MESSAGES_PAGE_LIMIT: usize = 100;
+
+/// Discord keeps at most this many pins per channel
+pub const PIN_LIMIT: usize = 50;
+
+/// Discord bulk deletes at most this many messages per request
+pub const BULK_DELETE_LIMIT: usize = 100;
+
+/// Discord only bulk deletes messages younger than this
+pub const BULK_DELETE_MAX_AGE_DAYS: i64 = 14;
+
+const MESSAGE_TYPE_REGULAR: u8 = 0;
+const MESSAGE_TYPE_PINS_ADD: u8 = 6;
+const CHANNEL_TYPE_TEXT: u8 = 0;
+/// overtaking writes complete in reverse order within each run of this many writes
+const OVERTAKE_WINDOW: usize = 8;
+
+/// Errors the fake guild reports, mirroring Discord's JSON error codes.
+#[derive(Debug)]
+pub enum FakeApiError {
+    UnknownGuild(GuildId),
+    UnknownChannel(ChannelId),
+    UnknownMessage(MessageId),
+    EmptyMessage,
+    ContentTooLong,
+    MissingChannelName,
+    MaxPins,
+    BulkDeleteAmount,
+    BulkDeleteTooOld,
+    RateLimited,
+}
+
+impl FakeApiError {
+    /// Discord JSON error code
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::UnknownGuild(_) => 10004,
+            Self::UnknownChannel(_) => 10003,
+            Self::UnknownMessage(_) => 10008,
+            Self::EmptyMessage => 50006,
+            Self::ContentTooLong | Self::MissingChannelName | Self::BulkDeleteAmount => 50035,
+            Self::MaxPins => 30003,
+            Self::BulkDeleteTooOld => 50034,
+            Self::RateLimited => 0,
+        }
+    }
+
+    /// HTTP status Discord answers with
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::UnknownGuild(_) | Self::UnknownChannel(_) | Self::UnknownMessage(_) => 404,
+            Self::RateLimited => 429,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for FakeApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownGuild(guild_id) => write!(f, "Unknown Guild: {guild_id}"),
+            Self::UnknownChannel(channel_id) => write!(f, "Unknown Channel: {channel_id}"),
+            Self::UnknownMessage(message_id) => write!(f, "Unknown Message: {message_id}"),
+            Self::EmptyMessage => write!(f, "Cannot send an empty message"),
+            Self::ContentTooLong => write!(
+                f,
+                "Invalid Form Body: content: Must be {MESSAGE_CONTENT_LIMIT} or fewer in length."
+            ),
+            Self::MissingChannelName => {
+                write!(f, "Invalid Form Body: name: This field is required")
+            }
+            Self::MaxPins => write!(f, "Maximum number of pins reached ({PIN_LIMIT})"),
+            Self::BulkDeleteAmount => write!(
+                f,
+                "Invalid Form Body: messages: Must be between 2 and {BULK_DELETE_LIMIT} in length."
+            ),
+            Self::BulkDeleteTooOld => write!(
+                f,
+                "You can only bulk delete messages that are under {BULK_DELETE_MAX_AGE_DAYS} days old."
+            ),
+            Self::RateLimited => write!(f, "You are being rate limited."),
+        }
+    }
+}
+
+impl std::error::Error for FakeApiError {}
+
+struct FakeChannel {
+    channel: GuildChannel,
+    messages: BTreeMap<u64, Message>,
+    /// oldest pin first
+    pins: Vec<MessageId>,
+    /// users reacting to a message with an emoji, keyed by message id and emoji data
+    reactions: HashMap<(u64, String), Vec<UserId>>,
+}
+
+/// In-process model of a single Discord guild.
+///
+/// Channels, messages and pins behave like the parts of the Discord API used by
+/// `DiscordStorage`: message ids are time-ordered snowflakes, pinning a message
+/// posts a `PinsAdd` system message, and content over 2000 characters is
+/// rejected.
+pub struct FakeGuild {
+    id: GuildId,
+    name: String,
+    user: User,
+    channels: BTreeMap<u64, FakeChannel>,
+    /// attachment id → file content
+    files: HashMap<u64, Vec<u8>>,
+    cdn_url: String,
+    /// age of the ids handed out, see [`FakeGuild::backdate`]
+    backdate: Duration,
+    last_snowflake: u64,
+    last_backdated_snowflake: u64,
+}
+
+impl FakeGuild {
+    pub fn new(name: impl Into<String>) -> Self {
+        let mut guild = Self {
+            id: GuildId(0),
+            name: name.into(),
+            user: Self::user(UserId(0), "gluesql", true),
+            channels: BTreeMap::new(),
+            files: HashMap::new(),
+            cdn_url: "https://cdn.discordapp.com".to_owned(),
+            backdate: Duration::zero(),
+            last_snowflake: 0,
+            last_backdated_snowflake: 0,
+        };
+
+        guild.id = GuildId(guild.next_snowflake());
+        guild.user = Self::user(UserId(guild.next_snowflake()), "gluesql", true);
+        guild
+    }
+
+    pub fn id(&self) -> GuildId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn current_user(&self) -> &User {
+        &self.user
+    }
+
+    /// base of the attachment urls handed out, e.g. a local emulator serving them
+    pub fn set_cdn_url(&mut self, cdn_url: impl Into<String>) {
+        self.cdn_url = cdn_url.into();
+    }
+
+    pub fn user(id: UserId, name: &str, bot: bool) -> User {
+        serde_json::from_value(json!({
+            "id": id.0.to_string(),
+            "username": name,
+            "discriminator": "0000",
+            "avatar": null,
+            "bot": bot,
+        }))
+        .expect("failed to build fake user")
+    }
+
+    /// Ids handed out from now on are created `age` ago, e.g. for messages older than the
+    /// bulk delete limit. `Duration::zero()` goes back to the current time.
+    pub fn backdate(&mut self, age: Duration) {
+        self.backdate = age;
+    }
+
+    /// next snowflake id, always greater than every id handed out before with the same backdate
+    pub fn next_snowflake(&mut self) -> u64 {
+        let created = Utc::now() - self.backdate;
+        let elapsed = (created.timestamp_millis() as u64).saturating_sub(DISCORD_EPOCH);
+        let last = if self.backdate.is_zero() {
+            &mut self.last_snowflake
+        } else {
+            &mut self.last_backdated_snowflake
+        };
+
+        let snowflake = (elapsed << 22).max(*last + 1);
+        *last = snowflake;
+        snowflake
+    }
+
+    pub fn channels(&self) -> HashMap<ChannelId, GuildChannel> {
+        self.channels
+            .values()
+            .map(|channel| (channel.channel.id, channel.channel.clone()))
+            .collect()
+    }
+
+    pub fn create_channel(&mut self, builder: CreateChannel) -> eyre::Result<GuildChannel> {
+        let id = self.next_snowflake();
+        let mut channel = json!({
+            "id": id.to_string(),
+            "guild_id": self.id.0.to_string(),
+            "type": CHANNEL_TYPE_TEXT,
+            "position": self.channels.len(),
+            "permission_overwrites": [],
+            "nsfw": false,
+        });
+
+        for (key, value) in builder.0 {
+            channel[key] = value;
+        }
+
+        let name = channel["name"]
+            .as_str()
+            .map(Self::normalize_channel_name)
+            .ok_or(FakeApiError::MissingChannelName)?;
+        channel["name"] = Json::String(name);
+
+        let channel: GuildChannel =
+            serde_json::from_value(channel).context("failed to build fake channel")?;
+
+        self.channels.insert(
+            id,
+            FakeChannel {
+                channel: channel.clone(),
+                messages: BTreeMap::new(),
+                pins: Vec::new(),
+                reactions: HashMap::new(),
+            },
+        );
+
+        Ok(channel)
+    }
+
+    pub fn rename_channel(
+        &mut self,
+        channel_id: ChannelId,
+        name: &str,
+    ) -> eyre::Result<GuildChannel> {
+        let channel = self.channel_mut(channel_id)?;
+        channel.channel.name = Self::normalize_channel_name(name);
+
+        Ok(channel.channel.clone())
+    }
+
+    pub fn delete_channel(&mut self, channel_id: ChannelId) -> eyre::Result<Channel> {
+        let channel = self
+            .channels
+            .remove(&channel_id.0)
+            .ok_or(FakeApiError::UnknownChannel(channel_id))?;
+
+        Ok(Channel::Guild(channel.channel))
+    }
+
+    /// newest message first, like the Discord message history endpoint
+    pub fn messages(&self, channel_id: ChannelId) -> eyre::Result<Vec<Message>> {
+        Ok(self
+            .channel(channel_id)?
+            .messages
+            .values()
+            .rev()
+            .cloned()
+            .collect())
+    }
+
+    /// one page of history, newest message first
+    ///
+    /// `after` wins over `before` and yields the oldest `limit` messages after it,
+    /// like the Discord message history endpoint.
+    pub fn messages_page(
+        &self,
+        channel_id: ChannelId,
+        before: Option<MessageId>,
+        after: Option<MessageId>,
+        limit: usize,
+    ) -> eyre::Result<Vec<Message>> {
+        let messages = &self.channel(channel_id)?.messages;
+
+        let page = match (before, after) {
+            (_, Some(after)) => {
+                let mut page = messages
+                    .range((Bound::Excluded(after.0), Bound::Unbounded))
+                    .take(limit)
+                    .map(|(_, message)| message.clone())
+                    .collect::<Vec<_>>();
+                page.reverse();
+                page
+            }
+            (Some(before), None) => messages
+                .range(..before.0)
+                .rev()
+                .take(limit)
+                .map(|(_, message)| message.clone())
+                .collect(),
+            (None, None) => messages.values().rev().take(limit).cloned().collect(),
+        };
+
+        Ok(page)
+    }
+
+    pub fn message(&self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<Message> {
+        let message = self
+            .channel(channel_id)?
+            .messages
+            .get(&message_id.0)
+            .ok_or(FakeApiError::UnknownMessage(message_id))?;
+
+        Ok(message.clone())
+    }
+
+    pub fn send_message(
+        &mut self,
+        channel_id: ChannelId,
+        content: impl Into<String>,
+    ) -> eyre::Result<Message> {
+        let author = self.user.clone();
+
+        self.send_message_as(channel_id, &author, content)
+    }
+
+    /// posts a message as someone other than the bot, e.g. a human chatting in a table channel
+    pub fn send_message_as(
+        &mut self,
+        channel_id: ChannelId,
+        author: &User,
+        content: impl Into<String>,
+    ) -> eyre::Result<Message> {
+        let content = content.into();
+        Self::validate_content(&content)?;
+
+        self.push_message(channel_id, author, content, MESSAGE_TYPE_REGULAR, None)
+    }
+
+    /// posts a reply to `message_id` as someone other than the bot
+    pub fn reply_as(
+        &mut self,
+        channel_id: ChannelId,
+        author: &User,
+        message_id: MessageId,
+        content: impl Into<String>,
+    ) -> eyre::Result<Message> {
+        let content = content.into();
+        Self::validate_content(&content)?;
+
+        self.push_message(
+            channel_id,
+            author,
+            content,
+            MESSAGE_TYPE_REGULAR,
+            Some(message_id),
+        )
+    }
+
+    pub fn edit_message(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: impl Into<String>,
+    ) -> eyre::Result<Message> {
+        let content = content.into();
+        Self::validate_content(&content)?;
+
+        let message = self
+            .channel_mut(channel_id)?
+            .messages
+            .get_mut(&message_id.0)
+            .ok_or(FakeApiError::UnknownMessage(message_id))?;
+
+        message.content = content;
+        message.edited_timestamp = Some(Timestamp::now());
+
+        Ok(message.clone())
+    }
+
+    pub fn send_message_with_files(
+        &mut self,
+        channel_id: ChannelId,
+        content: impl Into<String>,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        let message = self.send_message(channel_id, content)?;
+
+        self.set_attachments(channel_id, message.id, files)
+    }
+
+    /// replaces every attachment of the message with `files`
+    pub fn edit_message_with_files(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: impl Into<String>,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        self.edit_message(channel_id, message_id, content)?;
+
+        self.set_attachments(channel_id, message_id, files)
+    }
+
+    pub fn attachment(&self, attachment_id: AttachmentId) -> Option<&[u8]> {
+        self.files.get(&attachment_id.0).map(Vec::as_slice)
+    }
+
+    pub fn delete_message(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> eyre::Result<()> {
+        let channel = self.channel_mut(channel_id)?;
+
+        let message = channel
+            .messages
+            .remove(&message_id.0)
+            .ok_or(FakeApiError::UnknownMessage(message_id))?;
+        channel.pins.retain(|pin| pin != &message_id);
+        channel
+            .reactions
+            .retain(|(reacted, _), _| *reacted != message_id.0);
+
+        for attachment in message.attachments {
+            self.files.remove(&attachment.id.0);
+        }
+
+        Ok(())
+    }
+
+    /// reacts as `user_id`, reacting again with the same emoji changes nothing
+    pub fn react(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_id: UserId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        self.set_reaction(channel_id, message_id, user_id, reaction, true)
+    }
+
+    pub fn unreact(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_id: UserId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        self.set_reaction(channel_id, message_id, user_id, reaction, false)
+    }
+
+    fn set_reaction(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_id: UserId,
+        reaction: ReactionType,
+        reacted: bool,
+    ) -> eyre::Result<()> {
+        let current_user_id = self.user.id;
+        let channel = self.channel_mut(channel_id)?;
+
+        let message = channel
+            .messages
+            .get_mut(&message_id.0)
+            .ok_or(FakeApiError::UnknownMessage(message_id))?;
+        let users = channel
+            .reactions
+            .entry((message_id.0, reaction.as_data()))
+            .or_default();
+        users.retain(|user| *user != user_id);
+        if reacted {
+            users.push(user_id);
+        }
+
+        let count = users.len() as u64;
+        let me = users.contains(&current_user_id);
+        let position = message
+            .reactions
+            .iter()
+            .position(|existing| existing.reaction_type == reaction);
+        match position {
+            Some(position) if count == 0 => {
+                message.reactions.remove(position);
+            }
+            Some(position) => {
+                message.reactions[position].count = count;
+                message.reactions[position].me = me;
+            }
+            None if count > 0 => {
+                let reaction: MessageReaction = serde_json::from_value(json!({
+                    "count": count,
+                    "me": me,
+                    "emoji": reaction,
+                }))
+                .context("failed to build fake reaction")?;
+                message.reactions.push(reaction);
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// all or nothing, like Discord's bulk delete endpoint
+    pub fn delete_messages(
+        &mut self,
+        channel_id: ChannelId,
+        message_ids: &[MessageId],
+    ) -> eyre::Result<()> {
+        if !(2..=BULK_DELETE_LIMIT).contains(&message_ids.len()) {
+            return Err(FakeApiError::BulkDeleteAmount.into());
+        }
+
+        let oldest = Utc::now() - Duration::days(BULK_DELETE_MAX_AGE_DAYS);
+        let channel = self.channel(channel_id)?;
+        for message_id in message_ids {
+            if !channel.messages.contains_key(&message_id.0) {
+                return Err(FakeApiError::UnknownMessage(*message_id).into());
+            }
+
+            if message_id.created_at().unix_timestamp() < oldest.timestamp() {
+                return Err(FakeApiError::BulkDeleteTooOld.into());
+            }
+        }
+
+        for message_id in message_ids {
+            self.delete_message(channel_id, *message_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// newest pin first
+    pub fn pins(&self, channel_id: ChannelId) -> eyre::Result<Vec<Message>> {
+        let channel = self.channel(channel_id)?;
+
+        Ok(channel
+            .pins
+            .iter()
+            .rev()
+            .filter_map(|pin| channel.messages.get(&pin.0).cloned())
+            .collect())
+    }
+
+    pub fn pin(&mut self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()> {
+        let channel = self.channel_mut(channel_id)?;
+
+        if channel.pins.contains(&message_id) {
+            return Ok(());
+        }
+
+        if channel.pins.len() >= PIN_LIMIT {
+            return Err(FakeApiError::MaxPins.into());
+        }
+
+        channel
+            .messages
+            .get_mut(&message_id.0)
+            .ok_or(FakeApiError::UnknownMessage(message_id))?
+            .pinned = true;
+        channel.pins.push(message_id);
+        channel.channel.last_pin_timestamp = Some(Timestamp::now());
+
+        let author = self.user.clone();
+        self.push_message(
+            channel_id,
+            &author,
+            String::new(),
+            MESSAGE_TYPE_PINS_ADD,
+            Some(message_id),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn unpin(&mut self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()> {
+        let channel = self.channel_mut(channel_id)?;
+
+        let message = channel
+            .messages
+            .get_mut(&message_id.0)
+            .ok_or(FakeApiError::UnknownMessage(message_id))?;
+        message.pinned = false;
+        channel.pins.retain(|pin| pin != &message_id);
+        channel.channel.last_pin_timestamp = Some(Timestamp::now());
+
+        Ok(())
+    }
+
+    fn push_message(
+        &mut self,
+        channel_id: ChannelId,
+        author: &User,
+        content: String,
+        kind: u8,
+        reference: Option<MessageId>,
+    ) -> eyre::Result<Message> {
+        self.channel(channel_id)?;
+
+        let id = self.next_snowflake();
+        let message_reference = reference.map(|message_id| {
+            json!({
+                "message_id": message_id.0.to_string(),
+                "channel_id": channel_id.0.to_string(),
+                "guild_id": self.id.0.to_string(),
+            })
+        });
+
+        let message: Message = serde_json::from_value(json!({
+            "id": id.to_string(),
+            "channel_id": channel_id.0.to_string(),
+            "guild_id": self.id.0.to_string(),
+            "author": author,
+            "content": content,
+            "timestamp": MessageId(id).created_at(),
+            "edited_timestamp": null,
+            "tts": false,
+            "mention_everyone": false,
+            "mentions": [],
+            "mention_roles": [],
+            "attachments": [],
+            "embeds": [],
+            "pinned": false,
+            "type": kind,
+            "message_reference": message_reference,
+        }))
+        .context("failed to build fake message")?;
+
+        let channel = self.channel_mut(channel_id)?;
+        channel.messages.insert(id, message.clone());
+        channel.channel.last_message_id = Some(message.id);
+
+        Ok(message)
+    }
+
+    fn set_attachments(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        let mut attachments = Vec::with_capacity(files.len());
+        for MessageFile { filename, data } in files {
+            let id = self.next_snowflake();
+            let url = format!("{}/attachments/{channel_id}/{id}/{filename}", self.cdn_url);
+
+            let attachment: Attachment = serde_json::from_value(json!({
+                "id": id.to_string(),
+                "filename": filename,
+                "size": data.len(),
+                "url": url,
+                "proxy_url": url,
+                "height": null,
+                "width": null,
+            }))
+            .context("failed to build fake attachment")?;
+
+            self.files.insert(id, data);
+            attachments.push(attachment);
+        }
+
+        let message = self
+            .channel_mut(channel_id)?
+            .messages
+            .get_mut(&message_id.0)
+            .ok_or(FakeApiError::UnknownMessage(message_id))?;
+        let replaced = std::mem::replace(&mut message.attachments, attachments);
+        let message = message.clone();
+
+        for attachment in replaced {
+            self.files.remove(&attachment.id.0);
+        }
+
+        Ok(message)
+    }
+
+    fn channel(&self, channel_id: ChannelId) -> eyre::Result<&FakeChannel> {
+        let channel = self
+            .channels
+            .get(&channel_id.0)
+            .ok_or(FakeApiError::UnknownChannel(channel_id))?;
+
+        Ok(channel)
+    }
+
+    fn channel_mut(&mut self, channel_id: ChannelId) -> eyre::Result<&mut FakeChannel> {
+        let channel = self
+            .channels
+            .get_mut(&channel_id.0)
+            .ok_or(FakeApiError::UnknownChannel(channel_id))?;
+
+        Ok(channel)
+    }
+
+    fn validate_content(content: &str) -> eyre::Result<()> {
+        if content.is_empty() {
+            return Err(FakeApiError::EmptyMessage.into());
+        }
+
+        if content.chars().count() > MESSAGE_CONTENT_LIMIT {
+            return Err(FakeApiError::ContentTooLong.into());
+        }
+
+        Ok(())
+    }
+
+    /// text channel names are lowercased and spaces become dashes
+    fn normalize_channel_name(name: &str) -> String {
+        name.trim().to_lowercase().replace(' ', "-")
+    }
+}
+
+/// [`Transport`] backed by a [`FakeGuild`], for running `DiscordStorage` with no outside services.
+///
+/// Clones share the same guild, so a test can keep one around to inspect what the storage wrote.
+#[derive(Clone)]
+pub struct FakeDiscord {
+    guild: Rc<RefCell<FakeGuild>>,
+    cache: Arc<Cache>,
+    calls: Rc<RefCell<HashMap<&'static str, usize>>>,
+    writes: Rc<FakeWrites>,
+    rate_limited: Rc<Cell<bool>>,
+}
+
+/// Message writes awaited at the same time, see [`FakeDiscord::max_concurrent_writes`].
+#[derive(Debug, Default)]
+struct FakeWrites {
+    in_flight: Cell<usize>,
+    max_in_flight: Cell<usize>,
+    send_capacity: Cell<Option<usize>>,
+    overtaking: Cell<bool>,
+    started: Cell<usize>,
+}
+
+impl FakeDiscord {
+    pub fn new(guild_name: impl Into<String>) -> Self {
+        Self {
+            guild: Rc::new(RefCell::new(FakeGuild::new(guild_name))),
+            cache: Arc::new(Cache::new()),
+            calls: Rc::default(),
+            writes: Rc::default(),
+            rate_limited: Rc::default(),
+        }
+    }
+
+    /// how many times the `Transport` method `name` was called
+    pub fn calls(&self, name: &str) -> usize {
+        self.calls.borrow().get(name).copied().unwrap_or_default()
+    }
+
+    fn record(&self, name: &'static str) {
+        *self.calls.borrow_mut().entry(name).or_default() += 1;
+    }
+
+    /// records a request, which fails while [`FakeDiscord::set_rate_limited`] is on
+    fn request(&self, name: &'static str) -> eyre::Result<()> {
+        self.record(name);
+
+        match self.rate_limited.get() {
+            true => Err(FakeApiError::RateLimited.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Makes every request answer with Discord's 429 response, until it is turned off again.
+    pub fn set_rate_limited(&self, rate_limited: bool) {
+        self.rate_limited.set(rate_limited);
+    }
+
+    /// most message sends, edits and deletes that were awaited at the same time
+    pub fn max_concurrent_writes(&self) -> usize {
+        self.writes.max_in_flight.get()
+    }
+
+    /// what `send_capacity` reports, as if a rate limit bucket had this many requests left
+    pub fn set_send_capacity(&self, capacity: Option<usize>) {
+        self.writes.send_capacity.set(capacity);
+    }
+
+    /// Makes later writes reach the guild before earlier ones still in flight, like requests
+    /// racing over separate connections, so concurrent sends get ids out of order.
+    pub fn set_overtaking_writes(&self, overtaking: bool) {
+        self.writes.overtaking.set(overtaking);
+    }
+
+    /// yields while the write is counted as in flight, so concurrent writes overlap
+    async fn write(&self) {
+        let writes = &self.writes;
+        writes.in_flight.set(writes.in_flight.get() + 1);
+        writes
+            .max_in_flight
+            .set(writes.max_in_flight.get().max(writes.in_flight.get()));
+
+        let started = writes.started.get();
+        writes.started.set(started + 1);
+        let yields = if writes.overtaking.get() {
+            OVERTAKE_WINDOW - started % OVERTAKE_WINDOW
+        } else {
+            1
+        };
+        for _ in 0..yields {
+            tokio::task::yield_now().await;
+        }
+        writes.in_flight.set(writes.in_flight.get() - 1);
+    }
+
+    pub fn guild_id(&self) -> GuildId {
+        self.guild.borrow().id()
+    }
+
+    pub fn guild(&self) -> std::cell::Ref<'_, FakeGuild> {
+        self.guild.borrow()
+    }
+
+    pub fn guild_mut(&self) -> std::cell::RefMut<'_, FakeGuild> {
+        self.guild.borrow_mut()
+    }
+
+    pub fn into_storage(self) -> DiscordStorage<FakeDiscord> {
+        let storage_guild_id = self.guild_id();
+
+        DiscordStorage::new(self, storage_guild_id)
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for FakeDiscord {
+    fn serenity_cache(&self) -> Arc<Cache> {
+        Arc::clone(&self.cache)
+    }
+
+    fn latest_message_stream(
+        &self,
+        channel_id: ChannelId,
+    ) -> LocalBoxStream<'_, eyre::Result<Message>> {
+        self.record("latest_message_stream");
+        match self.guild.borrow().messages(channel_id) {
+            Ok(messages) => stream::iter(messages.into_iter().map(Ok)).boxed_local(),
+            Err(err) => stream::once(async move { Err(err) }).boxed_local(),
+        }
+    }
+
+    fn oldest_message_stream(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> LocalBoxStream<'_, eyre::Result<Message>> {
+        self.record("oldest_message_stream");
+        stream::try_unfold(Some(after), move |after| async move {
+            let after = match after {
+                Some(after) => after,
+                None => return Ok::<_, eyre::Report>(None),
+            };
+
+            let (page, next) = self.messages_after(channel_id, after).await?;
+
+            Ok(Some((stream::iter(page.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+        .boxed_local()
+    }
+
+    async fn messages_after(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> eyre::Result<(Vec<Message>, Option<MessageId>)> {
+        self.request("messages_page")?;
+        let mut page = self.guild.borrow().messages_page(
+            channel_id,
+            None,
+            Some(after),
+            MESSAGES_PAGE_LIMIT,
+        )?;
+        page.reverse();
+
+        let next = match page.last() {
+            Some(message) if page.len() == MESSAGES_PAGE_LIMIT => Some(message.id),
+            _ => None,
+        };
+
+        Ok((page, next))
+    }
+
+    async fn get_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> eyre::Result<Message> {
+        self.request("get_message")?;
+        self.guild.borrow().message(channel_id, message_id)
+    }
+
+    async fn get_pins(&self, channel_id: ChannelId) -> eyre::Result<Vec<Message>> {
+        self.request("get_pins")?;
+        self.guild.borrow().pins(channel_id)
+    }
+
+    async fn set_pin(&self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()> {
+        self.request("set_pin")?;
+        self.guild.borrow_mut().pin(channel_id, message_id)
+    }
+
+    async fn set_unpin(&self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()> {
+        self.request("set_unpin")?;
+        self.guild.borrow_mut().unpin(channel_id, message_id)
+    }
+
+    async fn send_message(&self, channel_id: ChannelId, content: String) -> eyre::Result<Message> {
+        self.request("send_message")?;
+        self.write().await;
+        self.guild.borrow_mut().send_message(channel_id, content)
+    }
+
+    async fn edit_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+    ) -> eyre::Result<Message> {
+        self.request("edit_message")?;
+        self.write().await;
+        self.guild
+            .borrow_mut()
+            .edit_message(channel_id, message_id, content)
+    }
+
+    async fn send_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        self.request("send_message_with_files")?;
+        self.write().await;
+        self.guild
+            .borrow_mut()
+            .send_message_with_files(channel_id, content, files)
+    }
+
+    async fn edit_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message> {
+        self.request("edit_message_with_files")?;
+        self.write().await;
+        self.guild
+            .borrow_mut()
+            .edit_message_with_files(channel_id, message_id, content, files)
+    }
+
+    async fn download_attachment(&self, attachment: &Attachment) -> eyre::Result<Vec<u8>> {
+        self.request("download_attachment")?;
+        self.guild
+            .borrow()
+            .attachment(attachment.id)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| eyre::eyre!("attachment not found: {}", attachment.url))
+    }
+
+    async fn delete_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> eyre::Result<()> {
+        self.request("delete_message")?;
+        self.write().await;
+        self.guild
+            .borrow_mut()
+            .delete_message(channel_id, message_id)
+    }
+
+    async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    ) -> eyre::Result<()> {
+        self.request("delete_messages")?;
+        self.write().await;
+        self.guild
+            .borrow_mut()
+            .delete_messages(channel_id, &message_ids)
+    }
+
+    async fn create_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        self.request("create_reaction")?;
+        self.write().await;
+        let mut guild = self.guild.borrow_mut();
+        let user_id = guild.current_user().id;
+
+        guild.react(channel_id, message_id, user_id, reaction)
+    }
+
+    async fn delete_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()> {
+        self.request("delete_reaction")?;
+        self.write().await;
+        let mut guild = self.guild.borrow_mut();
+        let user_id = guild.current_user().id;
+
+        guild.unreact(channel_id, message_id, user_id, reaction)
+    }
+
+    async fn send_capacity(&self, _channel_id: ChannelId) -> Option<usize> {
+        self.writes.send_capacity.get()
+    }
+
+    async fn get_channels(
+        &self,
+        guild_id: GuildId,
+    ) -> eyre::Result<HashMap<ChannelId, GuildChannel>> {
+        self.request("get_channels")?;
+        let guild = self.guild.borrow();
+        if guild_id != guild.id() {
+            return Err(FakeApiError::UnknownGuild(guild_id).into());
+        }
+
+        Ok(guild.channels())
+    }
+
+    async fn create_channel(
+        &self,
+        guild_id: GuildId,
+        builder: CreateChannel,
+    ) -> eyre::Result<GuildChannel> {
+        self.request("create_channel")?;
+        let mut guild = self.guild.borrow_mut();
+        if guild_id != guild.id() {
+            return Err(FakeApiError::UnknownGuild(guild_id).into());
+        }
+
+        guild.create_channel(builder)
+    }
+
+    async fn rename_channel(
+        &self,
+        channel_id: ChannelId,
+        name: String,
+    ) -> eyre::Result<GuildChannel> {
+        self.request("rename_channel")?;
+        self.guild.borrow_mut().rename_channel(channel_id, &name)
+    }
+
+    async fn delete_channel(&self, channel_id: ChannelId) -> eyre::Result<Channel> {
+        self.request("delete_channel")?;
+        self.guild.borrow_mut().delete_channel(channel_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::{futures::TryStreamExt, model::prelude::MessageType};
+
+    use super::*;
+
+    fn text_channel(discord: &FakeDiscord, name: &str) -> ChannelId {
+        let mut builder = CreateChannel::default();
+        builder.name(name);
+
+        discord.guild_mut().create_channel(builder).unwrap().id
+    }
+
+    #[test]
+    fn snowflakes_are_ordered() {
+        let mut guild = FakeGuild::new("test");
+
+        let first = guild.next_snowflake();
+        let second = guild.next_snowflake();
+
+        assert!(first < second);
+        assert!(MessageId(second).created_at() >= MessageId(first).created_at());
+    }
+
+    #[test]
+    fn channel_name_is_normalized() {
+        let discord = FakeDiscord::new("test");
+        let channel_id = text_channel(&discord, "Hello World");
+
+        let channels = discord.guild().channels();
+        assert_eq!(channels[&channel_id].name, "hello-world");
+    }
+
+    #[tokio::test]
+    async fn latest_message_first() {
+        let discord = FakeDiscord::new("test");
+        let channel_id = text_channel(&discord, "messages");
+
+        let first = discord
+            .send_message(channel_id, "1".to_owned())
+            .await
+            .unwrap();
+        let second = discord
+            .send_message(channel_id, "2".to_owned())
+            .await
+            .unwrap();
+
+        let messages = discord
+            .latest_message_stream(channel_id)
+            .map_ok(|message| message.id)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(messages, vec![second.id, first.id]);
+    }
+
+    #[tokio::test]
+    async fn pin_posts_system_message() {
+        let discord = FakeDiscord::new("test");
+        let channel_id = text_channel(&discord, "pins");
+
+        let message = discord
+            .send_message(channel_id, "schema".to_owned())
+            .await
+            .unwrap();
+        discord.set_pin(channel_id, message.id).await.unwrap();
+
+        let pins = discord.get_pins(channel_id).await.unwrap();
+        assert_eq!(pins.len(), 1);
+        assert!(pins[0].pinned);
+
+        let latest = discord.guild().messages(channel_id).unwrap();
+        assert_eq!(latest[0].kind, MessageType::PinsAdd);
+        assert_eq!(latest[1].kind, MessageType::Regular);
+    }
+
+    #[tokio::test]
+    async fn content_limit() {
+        let discord = FakeDiscord::new("test");
+        let channel_id = text_channel(&discord, "limit");
+
+        let content = "a".repeat(MESSAGE_CONTENT_LIMIT);
+        assert!(discord.send_message(channel_id, content).await.is_ok());
+
+        let content = "a".repeat(MESSAGE_CONTENT_LIMIT + 1);
+        assert!(discord.send_message(channel_id, content).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_message() {
+        let discord = FakeDiscord::new("test");
+        let channel_id = text_channel(&discord, "unknown");
+
+        let err = discord
+            .get_message(channel_id, MessageId(1))
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().starts_with("Unknown Message"));
+    }
+}
@@ -0,0 +1,178 @@
+pub mod fake;
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use serenity::{
+    builder::CreateChannel,
+    cache::Cache,
+    futures::stream::LocalBoxStream,
+    http::HttpError,
+    model::prelude::{
+        Attachment, Channel, ChannelId, GuildChannel, GuildId, Message, MessageId, ReactionType,
+    },
+};
+
+/// Discord epoch (2015-01-01T00:00:00Z) in milliseconds, the time message ids count from
+pub const DISCORD_EPOCH: u64 = 1_420_070_400_000;
+
+/// Discord's JSON error code for a message that does not exist
+pub const UNKNOWN_MESSAGE: isize = 10008;
+
+/// Whether `err` is Discord's Unknown Message error. Other failures, such as rate limits or
+/// network errors, say nothing about whether the message exists.
+pub fn is_unknown_message(err: &eyre::Report) -> bool {
+    err.chain()
+        .any(|err| match err.downcast_ref::<HttpError>() {
+            Some(HttpError::UnsuccessfulRequest(response)) => {
+                response.error.code == UNKNOWN_MESSAGE
+            }
+            _ => matches!(
+                err.downcast_ref::<fake::FakeApiError>(),
+                Some(fake::FakeApiError::UnknownMessage(_))
+            ),
+        })
+}
+
+/// A file uploaded as a message attachment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageFile {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+/// Operations `DiscordStorage` needs from a Discord guild.
+///
+/// [`crate::Discord`] implements this on top of a serenity client, other
+/// implementations can serve the same calls without a bot token or network.
+#[async_trait(?Send)]
+pub trait Transport: 'static {
+    fn serenity_cache(&self) -> Arc<Cache>;
+
+    /// newest message first
+    fn latest_message_stream(
+        &self,
+        channel_id: ChannelId,
+    ) -> LocalBoxStream<'_, eyre::Result<Message>>;
+
+    /// messages after `after` oldest first, each page of history is requested only once the
+    /// previous one is consumed, `MessageId(0)` starts from the first message of the channel
+    fn oldest_message_stream(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> LocalBoxStream<'_, eyre::Result<Message>>;
+
+    /// one page of [`Transport::oldest_message_stream`] as a single request, with the message
+    /// the next page starts after, `None` when this is the last page
+    async fn messages_after(
+        &self,
+        channel_id: ChannelId,
+        after: MessageId,
+    ) -> eyre::Result<(Vec<Message>, Option<MessageId>)>;
+
+    async fn get_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> eyre::Result<Message>;
+
+    async fn get_pins(&self, channel_id: ChannelId) -> eyre::Result<Vec<Message>>;
+
+    async fn set_pin(&self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()>;
+
+    async fn set_unpin(&self, channel_id: ChannelId, message_id: MessageId) -> eyre::Result<()>;
+
+    async fn send_message(&self, channel_id: ChannelId, content: String) -> eyre::Result<Message>;
+
+    async fn edit_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+    ) -> eyre::Result<Message>;
+
+    async fn send_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message>;
+
+    /// replaces every attachment of the message with `files`
+    async fn edit_message_with_files(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+        files: Vec<MessageFile>,
+    ) -> eyre::Result<Message>;
+
+    async fn download_attachment(&self, attachment: &Attachment) -> eyre::Result<Vec<u8>>;
+
+    async fn delete_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> eyre::Result<()>;
+
+    /// bulk delete of 2 to 100 messages, all younger than 14 days
+    async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    ) -> eyre::Result<()>;
+
+    /// reacts as the bot, reacting again with the same emoji changes nothing
+    async fn create_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()>;
+
+    /// removes the bot's reaction, if any
+    async fn delete_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        reaction: ReactionType,
+    ) -> eyre::Result<()>;
+
+    /// requests left in the current rate limit window for sending messages to `channel_id`,
+    /// `None` while the route has no known bucket
+    async fn send_capacity(&self, channel_id: ChannelId) -> Option<usize>;
+
+    async fn get_channels(
+        &self,
+        guild_id: GuildId,
+    ) -> eyre::Result<HashMap<ChannelId, GuildChannel>>;
+
+    async fn get_channel_id(
+        &self,
+        guild_id: GuildId,
+        channel_name: &str,
+    ) -> eyre::Result<Option<ChannelId>> {
+        let channels = self.get_channels(guild_id).await?;
+
+        Ok(channels
+            .into_iter()
+            .find_map(|(channel_id, channel)| (channel.name == channel_name).then_some(channel_id)))
+    }
+
+    /// required Manage Channels permission
+    async fn create_channel(
+        &self,
+        guild_id: GuildId,
+        builder: CreateChannel,
+    ) -> eyre::Result<GuildChannel>;
+
+    /// required Manage Channels permission
+    async fn rename_channel(
+        &self,
+        channel_id: ChannelId,
+        name: String,
+    ) -> eyre::Result<GuildChannel>;
+
+    async fn delete_channel(&self, channel_id: ChannelId) -> eyre::Result<Channel>;
+}
@@ -1,5 +1,12 @@
+use std::io::{Read, Write};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use serde::{de::DeserializeOwned, Serialize};
 
+/// fence of [`to_compressed_json`], base64 of the deflated minified JSON
+const DEFLATE_FENCE: &str = "```deflate";
+
 pub fn to_discord_json<T: Serialize>(data: &T) -> eyre::Result<String> {
     let text = serde_json::to_string_pretty(data)?;
 
@@ -11,8 +18,39 @@ pub fn to_discord_json<T: Serialize>(data: &T) -> eyre::Result<String> {
     ))
 }
 
+pub fn to_minified_json<T: Serialize>(data: &T) -> eyre::Result<String> {
+    let text = serde_json::to_string(data)?;
+
+    Ok(format!("```json\n{text}\n```"))
+}
+
+pub fn to_compressed_json<T: Serialize>(data: &T) -> eyre::Result<String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    serde_json::to_writer(&mut encoder, data)?;
+    encoder.flush()?;
+    let compressed = encoder.finish()?;
+
+    Ok(format!(
+        "{DEFLATE_FENCE}\n{}\n```",
+        BASE64.encode(compressed)
+    ))
+}
+
+/// decodes any of [`to_discord_json`], [`to_minified_json`] and [`to_compressed_json`]
 pub fn from_discord_json<T: DeserializeOwned>(text: &str) -> eyre::Result<T> {
     let text = text.trim();
+
+    if let Some(encoded) = text
+        .strip_prefix(DEFLATE_FENCE)
+        .and_then(|text| text.strip_suffix("```"))
+    {
+        let compressed = BASE64.decode(encoded.trim())?;
+        let mut json = Vec::new();
+        DeflateDecoder::new(compressed.as_slice()).read_to_end(&mut json)?;
+
+        return serde_json::from_slice(&json).map_err(Into::into);
+    }
+
     let text = text
         .strip_prefix(r#"```json"#)
         .unwrap_or(text)
@@ -21,3 +59,24 @@ pub fn from_discord_json<T: DeserializeOwned>(text: &str) -> eyre::Result<T> {
 
     serde_json::from_str(text).map_err(Into::into)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value as Json};
+
+    use super::{from_discord_json, to_compressed_json, to_discord_json, to_minified_json};
+
+    #[test]
+    fn every_format_decodes() {
+        let data = json!({ "Vec": [{ "I64": 1 }, { "Str": "glue ".repeat(100) }] });
+
+        let pretty = to_discord_json(&data).unwrap();
+        let minified = to_minified_json(&data).unwrap();
+        let compressed = to_compressed_json(&data).unwrap();
+        assert!(compressed.len() < minified.len() && minified.len() < pretty.len());
+
+        for text in [pretty, minified, compressed] {
+            assert_eq!(from_discord_json::<Json>(&text).unwrap(), data);
+        }
+    }
+}
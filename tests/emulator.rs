@@ -0,0 +1,183 @@
+#![cfg(feature = "emulator")]
+
+use gluesql_core::{
+    prelude::{Glue, Payload, Value},
+    store::{AlterTable, DataRow, Store, StoreMut},
+};
+use gluesql_discord_storage::{
+    emulator::Emulator,
+    storage::{DiscordStorage, StorageOptions},
+    transport::Transport,
+    Discord,
+};
+use serenity::model::prelude::{ChannelId, MessageId};
+
+const GUILD_NAME: &str = "GlueSQL Emulator Test";
+
+async fn discord() -> Discord {
+    let (addr, server) = Emulator::new(GUILD_NAME)
+        .bind(([127, 0, 0, 1], 0).into())
+        .unwrap();
+    tokio::spawn(server);
+
+    Discord::with_base_url("emulator-token", format!("http://{addr}")).await
+}
+
+#[tokio::test]
+async fn glue_roundtrip() {
+    let storage = discord().await.into_storage(GUILD_NAME).await.unwrap();
+    let mut glue = Glue::new(storage);
+
+    glue.execute_async("CREATE TABLE User (id INTEGER, name TEXT);")
+        .await
+        .unwrap();
+    glue.execute_async("INSERT INTO User VALUES (1, 'glue'), (2, 'sql');")
+        .await
+        .unwrap();
+    glue.execute_async("UPDATE User SET name = 'GLUE' WHERE id = 1;")
+        .await
+        .unwrap();
+    glue.execute_async("DELETE FROM User WHERE id = 2;")
+        .await
+        .unwrap();
+
+    let payloads = glue.execute_async("SELECT * FROM User;").await.unwrap();
+    assert_eq!(
+        payloads[0],
+        Payload::Select {
+            labels: vec!["id".to_owned(), "name".to_owned()],
+            rows: vec![vec![Value::I64(1), Value::Str("GLUE".to_owned())]],
+        }
+    );
+
+    glue.execute_async("DROP TABLE User;").await.unwrap();
+}
+
+#[tokio::test]
+async fn storage_over_http() {
+    let mut storage = discord().await.into_storage(GUILD_NAME).await.unwrap();
+
+    storage
+        .insert_schema(&gluesql_core::data::Schema {
+            table_name: "Item".to_owned(),
+            column_defs: None,
+            indexes: vec![],
+            engine: None,
+            created: gluesql_core::chrono::Utc::now().naive_utc(),
+        })
+        .await
+        .unwrap();
+    assert!(storage.fetch_schema("Item").await.unwrap().is_some());
+
+    let rows = (0..120)
+        .map(|i| gluesql_core::store::DataRow::Map([("id".to_owned(), Value::I64(i))].into()))
+        .collect();
+    storage.append_data("Item", rows).await.unwrap();
+
+    let scanned = storage
+        .scan_data("Item")
+        .await
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(scanned.len(), 120);
+
+    storage.rename_schema("Item", "Product").await.unwrap();
+    assert!(storage.fetch_schema("Item").await.unwrap().is_none());
+    assert_eq!(
+        storage
+            .fetch_schema("Product")
+            .await
+            .unwrap()
+            .unwrap()
+            .table_name,
+        "Product"
+    );
+
+    storage.delete_schema("Product").await.unwrap();
+    assert!(storage.fetch_schema("Product").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn attachments_over_http() {
+    let discord = discord().await;
+    let storage_guild_id = discord.get_guild_info(GUILD_NAME).await.unwrap().id;
+    let options = StorageOptions {
+        attachment_threshold: Some(200),
+        bytea_attachments: true,
+        ..StorageOptions::default()
+    };
+    let mut storage = DiscordStorage::with_options(discord, storage_guild_id, options);
+
+    storage
+        .insert_schema(&gluesql_core::data::Schema {
+            table_name: "File".to_owned(),
+            column_defs: None,
+            indexes: vec![],
+            engine: None,
+            created: gluesql_core::chrono::Utc::now().naive_utc(),
+        })
+        .await
+        .unwrap();
+
+    let row = |name: &str| {
+        DataRow::Map(
+            [
+                ("name".to_owned(), Value::Str(name.to_owned())),
+                ("data".to_owned(), Value::Bytea(vec![0, 1, 255])),
+            ]
+            .into(),
+        )
+    };
+    storage
+        .append_data("File", vec![row(&"glue".repeat(100))])
+        .await
+        .unwrap();
+
+    let scanned = storage
+        .scan_data("File")
+        .await
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(scanned[0].1, row(&"glue".repeat(100)));
+
+    let key = scanned[0].0.clone();
+    storage
+        .insert_data("File", vec![(key.clone(), row("sql"))])
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.fetch_data("File", &key).await.unwrap(),
+        Some(row("sql"))
+    );
+
+    storage.delete_schema("File").await.unwrap();
+}
+
+#[tokio::test]
+async fn http_errors_are_mapped() {
+    let discord = discord().await;
+    let guild_id = discord.get_guild_info(GUILD_NAME).await.unwrap().id;
+
+    let mut builder = serenity::builder::CreateChannel::default();
+    builder.name("errors");
+    let channel = Transport::create_channel(&discord, guild_id, builder)
+        .await
+        .unwrap();
+
+    let err = Transport::get_message(&discord, channel.id, MessageId(1))
+        .await
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("Unknown Message"));
+
+    let err = Transport::get_pins(&discord, ChannelId(1))
+        .await
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("Unknown Channel"));
+
+    let err = Transport::delete_message(&discord, channel.id, MessageId(1))
+        .await
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("Unknown Message"));
+}